@@ -0,0 +1,128 @@
+use legion::*;
+use serde::{ Serialize, Deserialize };
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, clamp };
+
+// Per-species relationships between trunk diameter and other dimensions.
+// Diameter at breast height (DBH) is in centimeters, everything else in
+// meters and kilograms.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Allometry {
+  pub dbh_per_size: f32, // DBH per unit of size
+  pub growth_shape: f32, // Richards shape, how late growth slows down near max_size
+  pub max_height: f32,
+  pub height_rate: f32, // Chapman-Richards height curve, height = max_height * (1 - e^(-rate * dbh))^shape
+  pub height_shape: f32,
+  pub crown_coefficient: f32, // crown radius = coefficient * dbh^exponent
+  pub crown_exponent: f32,
+  pub leaf_area_coefficient: f32, // leaf area = coefficient * dbh^exponent
+  pub leaf_area_exponent: f32,
+  pub biomass_coefficient: f32, // above-ground biomass = coefficient * dbh^exponent
+  pub biomass_exponent: f32,
+}
+
+// Physical dimensions of a tree derived from its size, used for rendering
+// and forestry metrics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dimensions {
+  pub height: f32,
+  pub dbh: f32,
+  pub crown_radius: f32,
+  pub leaf_area: f32,
+  pub biomass: f32,
+}
+
+impl Dimensions {
+  pub fn of(properties: &TreeProperties, tree_type: &TreeType) -> Self {
+    Dimensions {
+      height: height(properties, tree_type),
+      dbh: dbh(properties, tree_type),
+      crown_radius: crown_radius(properties, tree_type),
+      leaf_area: leaf_area(properties, tree_type),
+      biomass: biomass(properties, tree_type),
+    }
+  }
+}
+
+pub fn dbh(properties: &TreeProperties, tree_type: &TreeType) -> f32 {
+  properties.size.max(0.0) * tree_type.allometry.dbh_per_size
+}
+
+pub fn height(properties: &TreeProperties, tree_type: &TreeType) -> f32 {
+  let allometry = &tree_type.allometry;
+  let dbh = dbh(properties, tree_type);
+  allometry.max_height * (1.0 - (-allometry.height_rate * dbh).exp()).powf(allometry.height_shape)
+}
+
+pub fn crown_radius(properties: &TreeProperties, tree_type: &TreeType) -> f32 {
+  let allometry = &tree_type.allometry;
+  allometry.crown_coefficient * dbh(properties, tree_type).powf(allometry.crown_exponent)
+}
+
+pub fn leaf_area(properties: &TreeProperties, tree_type: &TreeType) -> f32 {
+  let allometry = &tree_type.allometry;
+  allometry.leaf_area_coefficient * dbh(properties, tree_type).powf(allometry.leaf_area_exponent)
+}
+
+pub fn biomass(properties: &TreeProperties, tree_type: &TreeType) -> f32 {
+  let allometry = &tree_type.allometry;
+  allometry.biomass_coefficient * dbh(properties, tree_type).powf(allometry.biomass_exponent)
+}
+
+// Part of potential growth the tree is able to realize at its size. Follows
+// the Richards curve, so growth slows down smoothly and stops at max_size.
+pub fn growth_saturation(size: f32, tree_type: &TreeType) -> f32 {
+  let relative = clamp(size / tree_type.max_size, 0.0, 1.0);
+  1.0 - relative.powf(tree_type.allometry.growth_shape)
+}
+
+#[system(par_for_each)]
+pub fn update_dimensions(
+  species: &SpeciesId,
+  properties: &TreeProperties,
+  dimensions: &mut Dimensions,
+  #[resource] registry: &SpeciesRegistry,
+) {
+  *dimensions = Dimensions::of(properties, registry.get(*species));
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn oak() -> TreeType {
+    let registry = SpeciesRegistry::load();
+    registry.get(registry.find("oak").unwrap()).clone()
+  }
+
+  #[test]
+  fn dimensions_grow_with_size() {
+    let oak = oak();
+    let small = Dimensions::of(&TreeProperties::new(0.0, 1.0), &oak);
+    let big = Dimensions::of(&TreeProperties::new(0.0, 10.0), &oak);
+    assert!(small.height < big.height);
+    assert!(small.dbh < big.dbh);
+    assert!(small.crown_radius < big.crown_radius);
+    assert!(small.leaf_area < big.leaf_area);
+    assert!(small.biomass < big.biomass);
+    assert_eq!(Dimensions::of(&TreeProperties::new(0.0, 0.0), &oak).height, 0.0);
+  }
+
+  #[test]
+  fn height_saturates_at_max_height() {
+    let oak = oak();
+    let giant = TreeProperties::new(0.0, 1000.0);
+    assert!(height(&giant, &oak) <= oak.allometry.max_height);
+    assert!(height(&giant, &oak) > 0.99 * oak.allometry.max_height);
+  }
+
+  #[test]
+  fn growth_slows_down_near_max_size() {
+    let oak = oak();
+    assert_eq!(growth_saturation(0.0, &oak), 1.0);
+    assert!(growth_saturation(oak.max_size / 2.0, &oak) < 1.0);
+    assert!(growth_saturation(oak.max_size * 0.9, &oak) < growth_saturation(oak.max_size / 2.0, &oak));
+    assert_eq!(growth_saturation(oak.max_size, &oak), 0.0);
+    assert_eq!(growth_saturation(oak.max_size * 2.0, &oak), 0.0);
+  }
+}
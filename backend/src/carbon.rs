@@ -0,0 +1,399 @@
+use legion::*;
+use legion::world::SubWorld;
+use serde::{ Serialize, Deserialize };
+use std::fs::{ self, OpenOptions };
+use std::io::Write;
+use std::path::Path;
+use super::timer::Timers;
+use super::settings::Settings;
+use super::map::Map;
+use super::movements::Position;
+use super::raster::Raster;
+use super::allometry::Dimensions;
+use super::grass::GrassLayer;
+use super::harvest::HarvestRecord;
+use super::mortality::Deadwood;
+use super::tree::TreeProperties;
+
+static CARBON_CONFIG: &str = include_str!("../config/carbon.yaml");
+const CARBON_CSV_HEADER: &str = "year,live,deadwood,litter,soil,total,sequestration,respiration,harvest";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CarbonConfig {
+  pub carbon_fraction: f32,
+  pub litter_fall: f32,
+  pub litter_decay: f32,
+  pub deadwood_decay: f32,
+  pub humification: f32,
+  pub soil_decay: f32,
+  pub soil_carbon: f32,
+}
+
+impl CarbonConfig {
+  pub fn load() -> Self {
+    serde_yaml::from_str(CARBON_CONFIG).unwrap()
+  }
+}
+
+// Carbon pools in kilograms, per square meter in cells
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CarbonStock {
+  pub live: f32, // trees and grass
+  pub deadwood: f32,
+  pub litter: f32,
+  pub soil: f32,
+}
+
+impl CarbonStock {
+  pub fn total(&self) -> f32 {
+    self.live + self.deadwood + self.litter + self.soil
+  }
+
+  fn add(&self, other: &CarbonStock) -> CarbonStock {
+    CarbonStock {
+      live: self.live + other.live,
+      deadwood: self.deadwood + other.deadwood,
+      litter: self.litter + other.litter,
+      soil: self.soil + other.soil,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CarbonBudget {
+  pub year: u32,
+  pub stock: CarbonStock, // at the end of the year
+  pub sequestration: f32, // change of the total stock during the year, harvest left out
+  pub respiration: f32, // released by decay of dead organic matter
+  pub harvest: f32, // removed from the stand with felled trees
+}
+
+impl CarbonBudget {
+  pub fn csv_row(&self) -> String {
+    let stock = &self.stock;
+    format!(
+      "{},{},{},{},{},{},{},{},{}",
+      self.year, stock.live, stock.deadwood, stock.litter, stock.soil, stock.total(), self.sequestration, self.respiration, self.harvest
+    )
+  }
+}
+
+// Part of carbon left after decay with the yearly rate
+fn remaining(rate: f32, years: f32) -> f32 {
+  (1.0 - rate).powf(years.max(0.0))
+}
+
+// Part of a dead tree not decayed yet
+pub fn deadwood_left(config: &CarbonConfig, age_years: f32) -> f32 {
+  remaining(config.deadwood_decay, age_years)
+}
+
+pub struct CarbonAccounts {
+  config: CarbonConfig,
+  cells: Raster<CarbonStock>,
+  years: f32, // simulated since the start
+  year_start: Option<f32>, // total stock when the current year started
+  respiration: f32, // during the current year
+  harvest: f32, // during the current year
+  harvested: f32, // biomass in the harvest record at the last tick
+}
+
+impl CarbonAccounts {
+  pub fn covering(map: &Map, cell_size: f32, config: CarbonConfig) -> Self {
+    let mut cells = Raster::covering(map, cell_size, CarbonStock::default());
+    for ix in 0..cells.len() {
+      let moisture = map.ground_type(&cells.cell_center(ix)).map_or(0.0, |ground| ground.moisture());
+      cells.cell_mut(ix).soil = config.soil_carbon * moisture;
+    }
+    CarbonAccounts {
+      config,
+      cells,
+      years: 0.0,
+      year_start: None,
+      respiration: 0.0,
+      harvest: 0.0,
+      harvested: 0.0,
+    }
+  }
+
+  pub fn config(&self) -> &CarbonConfig {
+    &self.config
+  }
+
+  // Stocks of the whole map in kilograms
+  pub fn totals(&self) -> CarbonStock {
+    let area = self.area();
+    let sum = self.cells.iter().fold(CarbonStock::default(), |sum, cell| sum.add(cell));
+    CarbonStock {
+      live: sum.live * area,
+      deadwood: sum.deadwood * area,
+      litter: sum.litter * area,
+      soil: sum.soil * area,
+    }
+  }
+
+  fn area(&self) -> f32 {
+    self.cells.cell_size() * self.cells.cell_size()
+  }
+
+  // Live and deadwood pools are counted anew every tick
+  fn reset(&mut self, grass: &GrassLayer) {
+    let carbon_fraction = self.config.carbon_fraction;
+    for ix in 0..self.cells.len() {
+      let grass = grass.biomass_at(&self.cells.cell_center(ix));
+      let cell = self.cells.cell_mut(ix);
+      cell.live = grass * carbon_fraction;
+      cell.deadwood = 0.0;
+    }
+  }
+
+  // Living tree with biomass in kilograms, it drops litter
+  fn add_tree(&mut self, position: &Position, biomass: f32, years: f32) {
+    let carbon = biomass * self.config.carbon_fraction / self.area();
+    let litter_fall = self.config.litter_fall;
+    if let Some(cell) = self.cells.get_mut(position) {
+      cell.live += carbon;
+      cell.litter += carbon * litter_fall * years;
+    }
+  }
+
+  // Dead tree with biomass it had when it died, decaying since then
+  fn add_deadwood(&mut self, position: &Position, biomass: f32, age_years: f32, years: f32) {
+    let area = self.area();
+    let carbon = biomass * self.config.carbon_fraction / area;
+    let rate = self.config.deadwood_decay;
+    let decayed = carbon * (remaining(rate, age_years - years) - remaining(rate, age_years));
+    let humification = self.config.humification;
+    if let Some(cell) = self.cells.get_mut(position) {
+      cell.deadwood += carbon * remaining(rate, age_years);
+      cell.soil += decayed * humification;
+      self.respiration += decayed * (1.0 - humification) * area;
+    }
+  }
+
+  // Litter decays partly into soil, soil carbon is slowly respired
+  fn decompose(&mut self, years: f32) {
+    let config = &self.config;
+    let litter_left = remaining(config.litter_decay, years);
+    let soil_left = remaining(config.soil_decay, years);
+    let mut respiration = 0.0;
+    for cell in self.cells.iter_mut() {
+      let decayed = cell.litter * (1.0 - litter_left);
+      let soil_decayed = cell.soil * (1.0 - soil_left);
+      cell.litter -= decayed;
+      cell.soil += decayed * config.humification - soil_decayed;
+      respiration += decayed * (1.0 - config.humification) + soil_decayed;
+    }
+    self.respiration += respiration * self.area();
+  }
+
+  // Felled trees leave the live pool, their carbon is booked as harvest
+  // so it does not count as release to the atmosphere
+  fn add_harvest(&mut self, record: &HarvestRecord) {
+    let harvested = record.total().biomass;
+    self.harvest += (harvested - self.harvested) * self.config.carbon_fraction;
+    self.harvested = harvested;
+  }
+
+  // Returns the budget of the year when a new year starts
+  fn close_tick(&mut self, years: f32) -> Option<CarbonBudget> {
+    let stock = self.totals();
+    let year_start = *self.year_start.get_or_insert(stock.total());
+    let year = self.years as u32;
+    self.years += years;
+    if self.years as u32 > year {
+      let budget = CarbonBudget {
+        year,
+        stock,
+        sequestration: stock.total() - year_start + self.harvest,
+        respiration: self.respiration,
+        harvest: self.harvest,
+      };
+      self.year_start = Some(stock.total());
+      self.respiration = 0.0;
+      self.harvest = 0.0;
+      Some(budget)
+    } else {
+      None
+    }
+  }
+}
+
+fn append_budget(directory: &str, budget: &CarbonBudget) {
+  let path = Path::new(directory).join("carbon.csv");
+  let written = fs::create_dir_all(directory).and_then(|_| {
+    let new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if new {
+      writeln!(file, "{}", CARBON_CSV_HEADER)?;
+    }
+    writeln!(file, "{}", budget.csv_row())
+  });
+  if let Err(error) = written {
+    println!("cannot write carbon budget to {:?}: {}", path, error);
+  }
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(Dimensions)]
+#[read_component(TreeProperties)]
+#[read_component(Deadwood)]
+pub fn update_carbon(
+  world: &mut SubWorld,
+  #[resource] carbon: &mut CarbonAccounts,
+  #[resource] grass: &GrassLayer,
+  #[resource] record: &HarvestRecord,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+) {
+  let years = time.long.elapsed_seconds / settings.seconds_in_year();
+  carbon.reset(grass);
+  let mut trees = <(&Position, &Dimensions)>::query().filter(component::<TreeProperties>());
+  for (position, dimensions) in trees.iter(world) {
+    carbon.add_tree(position, dimensions.biomass, years);
+  }
+  let mut deadwood = <(&Position, &Dimensions, &Deadwood)>::query();
+  for (position, dimensions, deadwood) in deadwood.iter(world) {
+    carbon.add_deadwood(position, dimensions.biomass, deadwood.age / settings.seconds_in_year(), years);
+  }
+  carbon.decompose(years);
+  carbon.add_harvest(record);
+  if let Some(budget) = carbon.close_tick(years) {
+    if let Some(directory) = &settings.output_directory {
+      append_budget(directory, &budget);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use num::traits::Zero;
+  use super::super::grass::GrassConfig;
+  use super::super::species::SpeciesRegistry;
+  use super::super::tree::place_tree_test;
+
+  fn resources(years_per_tick: f32, output_directory: Option<String>) -> Resources {
+    let settings = Settings { output_directory, ..Settings::default() };
+    let map = Map::test_square(10.0, 10.0);
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = years_per_tick * settings.seconds_in_year();
+    let mut resources = Resources::default();
+    resources.insert(CarbonAccounts::covering(&map, 1.0, CarbonConfig::load()));
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(HarvestRecord::default());
+    resources.insert(timers);
+    resources.insert(settings);
+    resources
+  }
+
+  fn run(world: &mut World, resources: &mut Resources, ticks: usize) {
+    let mut schedule = Schedule::builder()
+      .add_system(update_carbon_system())
+      .build();
+    for _ in 0..ticks {
+      schedule.execute(world, resources);
+    }
+  }
+
+  // Directory for exported budgets, emptied
+  fn output(name: &str) -> std::path::PathBuf {
+    let directory = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&directory);
+    directory
+  }
+
+  // Budget rows written into the directory, the header left out
+  fn budget_rows(directory: &Path) -> Vec<String> {
+    let csv = fs::read_to_string(directory.join("carbon.csv")).unwrap_or_default();
+    let _ = fs::remove_dir_all(directory);
+    assert!(csv.is_empty() || csv.starts_with(CARBON_CSV_HEADER));
+    csv.lines().skip(1).map(String::from).collect()
+  }
+
+  fn column(row: &str, name: &str) -> f32 {
+    let ix = CARBON_CSV_HEADER.split(',').position(|column| column == name).unwrap();
+    row.split(',').nth(ix).unwrap().parse().unwrap()
+  }
+
+  #[test]
+  fn live_carbon_follows_allometry() {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    let tree = place_tree_test(&mut world, &registry, "oak", Position::zero(), 0.0, 5.0).unwrap();
+    let biomass = world.entry(tree).unwrap().get_component::<Dimensions>().unwrap().biomass;
+    let mut resources = resources(0.01, None);
+    run(&mut world, &mut resources, 1);
+    let carbon = resources.get::<CarbonAccounts>().unwrap();
+    let grass = resources.get::<GrassLayer>().unwrap().total();
+    let config = CarbonConfig::load();
+    let live = carbon.totals().live;
+    assert!((live - (biomass + grass) * config.carbon_fraction).abs() < 1e-3 * live);
+    assert!(carbon.cells.get(&Position::zero()).unwrap().litter > 0.0);
+    assert!((carbon.totals().soil - config.soil_carbon * 0.7 * 100.0).abs() < 1.0);
+  }
+
+  #[test]
+  fn deadwood_decays_into_soil() {
+    let config = CarbonConfig::load();
+    let mut carbon = CarbonAccounts::covering(&Map::test_square(10.0, 10.0), 1.0, CarbonConfig { soil_decay: 0.0, ..config.clone() });
+    let soil = carbon.totals().soil;
+    carbon.add_deadwood(&Position::zero(), 100.0, 0.0, 0.0);
+    let fresh = carbon.totals().deadwood;
+    assert_eq!(fresh, 100.0 * config.carbon_fraction);
+    assert_eq!(carbon.totals().soil, soil);
+
+    carbon.reset(&GrassLayer::covering(&Map::test_square(10.0, 10.0), 1.0, GrassConfig::load()));
+    carbon.add_deadwood(&Position::zero(), 100.0, 10.0, 10.0);
+    let decayed = fresh - carbon.totals().deadwood;
+    assert!(decayed > 0.0);
+    assert!((carbon.totals().soil - soil - decayed * config.humification).abs() < 1e-3);
+    assert!((carbon.respiration - decayed * (1.0 - config.humification)).abs() < 1e-3);
+  }
+
+  #[test]
+  fn yearly_budget_is_exported() {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    place_tree_test(&mut world, &registry, "oak", Position::zero(), 0.0, 5.0).unwrap();
+    let directory = output("carbon_export_test");
+    let mut resources = resources(0.25, Some(directory.to_string_lossy().into()));
+    run(&mut world, &mut resources, 3);
+    assert!(!directory.join("carbon.csv").exists());
+    run(&mut world, &mut resources, 5);
+    let rows = budget_rows(&directory);
+    assert_eq!(rows.iter().map(|row| column(row, "year")).collect::<Vec<_>>(), vec!(0.0, 1.0));
+    assert!(rows.iter().all(|row| column(row, "respiration") > 0.0 && column(row, "harvest") == 0.0));
+    // a single tree drops less litter than the soil respires
+    assert!(column(&rows[1], "sequestration") < 0.0);
+  }
+
+  #[test]
+  fn harvest_is_not_counted_as_release() {
+    let registry = SpeciesRegistry::load();
+    let oak_species = registry.find("oak").unwrap();
+    let year_with = |felled: bool| {
+      let mut world = World::default();
+      let oak = place_tree_test(&mut world, &registry, "oak", Position::zero(), 0.0, 10.0).unwrap();
+      let directory = output(if felled { "carbon_harvest_test" } else { "carbon_no_harvest_test" });
+      let mut resources = resources(0.25, Some(directory.to_string_lossy().into()));
+      run(&mut world, &mut resources, 1);
+      let biomass = world.entry(oak).unwrap().get_component::<Dimensions>().unwrap().biomass;
+      if felled {
+        let dimensions = world.entry(oak).unwrap().get_component::<Dimensions>().unwrap().clone();
+        world.remove(oak);
+        resources.get_mut::<HarvestRecord>().unwrap().add(oak_species, &dimensions);
+      }
+      run(&mut world, &mut resources, 3);
+      (budget_rows(&directory).remove(0), biomass)
+    };
+    let (kept, _) = year_with(false);
+    let (felled, biomass) = year_with(true);
+    let harvest = column(&felled, "harvest");
+    assert_eq!(column(&kept, "harvest"), 0.0);
+    assert!((harvest - biomass * CarbonConfig::load().carbon_fraction).abs() < 1e-3 * harvest);
+    assert!(column(&felled, "live") < column(&kept, "live"));
+    assert!((column(&felled, "sequestration") - column(&kept, "sequestration")).abs() < harvest / 10.0);
+  }
+}
@@ -1,6 +1,7 @@
 use legion::*;
 use serde::{ Serialize, Deserialize };
 use super::timer::Timers;
+use super::settings::Settings;
 use super::interpolations::circle_map;
 
 static CLIMATE: &str = include_str!("../config/climate.yaml");
@@ -9,6 +10,10 @@ static CLIMATE: &str = include_str!("../config/climate.yaml");
 struct Zone {
   name: String,
   monthly_temp: Vec<f32>,
+  wind_direction: f32, // degrees, direction wind blows to
+  wind_speed: f32,
+  humidity: f32,
+  monthly_precipitation: Vec<f32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,9 +22,19 @@ struct ClimateSettings {
   dayly_tempreture_floating: f32
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wind {
+  pub direction: f32, // radians
+  pub speed: f32,
+}
+
 pub struct Weather {
   dayly_curve: Vec<f32>,
   pub current_tempreture: f32,
+  pub wind: Wind,
+  pub humidity: f32,
+  pub current_precipitation: f32, // millimeters per month
+  precipitation_curve: Vec<f32>,
   yearly_curve: Vec<f32>
 }
 
@@ -29,8 +44,10 @@ impl ClimateSettings {
 
 
 #[system]
-pub fn weather(#[resource] weather_resource: &mut Weather, #[resource] timers: &Timers) {
-  weather_resource.current_tempreture = weather_resource.calculate_normal_tempreture(timers);
+pub fn weather(#[resource] weather_resource: &mut Weather, #[resource] timers: &Timers, #[resource] settings: &Settings) {
+  weather_resource.current_tempreture = weather_resource.calculate_normal_tempreture(timers, settings);
+  let local_year_time = settings.hemisphere.local_year_time(timers.time_of_year);
+  weather_resource.current_precipitation = circle_map(local_year_time, &weather_resource.precipitation_curve);
 }
 
 impl Weather {
@@ -49,6 +66,13 @@ impl Weather {
 
       Weather {
         current_tempreture: 0.0,
+        wind: Wind {
+          direction: zone.wind_direction.to_radians(),
+          speed: zone.wind_speed
+        },
+        humidity: zone.humidity,
+        current_precipitation: 0.0,
+        precipitation_curve: zone.monthly_precipitation.clone(),
         dayly_curve,
         yearly_curve: zone.monthly_temp.clone()
       }
@@ -57,10 +81,11 @@ impl Weather {
     }
   }
 
-  fn calculate_normal_tempreture(&self, timers: &Timers) -> f32 {
+  fn calculate_normal_tempreture(&self, timers: &Timers, settings: &Settings) -> f32 {
     let dayly_fluctuation = circle_map(timers.time_of_day, &self.dayly_curve);
-    // time of year - is a param from first day of spring to last day of winter
-    let yearly_fluctuation = circle_map(timers.time_of_year, &self.yearly_curve);
+    // local time of year - is a param from first day of spring to last day of winter
+    let local_year_time = settings.hemisphere.local_year_time(timers.time_of_year);
+    let yearly_fluctuation = circle_map(local_year_time, &self.yearly_curve);
 
 
     dayly_fluctuation + yearly_fluctuation
@@ -71,7 +96,7 @@ impl Weather {
 #[cfg(test)]
 mod test {
   use super::*;
-  use super::super::settings::Settings;
+  use super::super::phenology::Hemisphere;
 
   #[test]
   fn check_that_temp_is_ok() {
@@ -80,12 +105,35 @@ mod test {
     let settings = Settings {
       days_in_season: 1,
       day_duration: 1.0,
-      climate_zone: zone_name.to_owned()
+      climate_zone: zone_name.to_owned(),
+      ..Settings::default()
     };
     let mut timers = Timers::default();
     timers.update_fields(&settings);
-    let t = w.calculate_normal_tempreture(&timers);
-    assert_eq!(t, 24.0);
+    let t = w.calculate_normal_tempreture(&timers, &settings);
+    assert!((t - 24.0).abs() < 1e-4);
+
+  }
 
+  #[test]
+  fn southern_winter_falls_on_northern_summer() {
+    let weather = |hemisphere, time_of_year| {
+      let timers = Timers { time_of_year, time_of_day: 0.25, ..Timers::default() };
+      let mut resources = Resources::default();
+      resources.insert(Weather::prepare("moderate"));
+      resources.insert(Settings { hemisphere, ..Settings::default() });
+      resources.insert(timers);
+      let mut schedule = Schedule::builder().add_system(weather_system()).build();
+      schedule.execute(&mut World::default(), &mut resources);
+      let weather = resources.get::<Weather>().unwrap();
+      (weather.current_tempreture, weather.current_precipitation)
+    };
+    let july = 4.0 / 12.0;
+    let north = weather(Hemisphere::Northern, july);
+    let south = weather(Hemisphere::Southern, july);
+    assert_eq!(south, weather(Hemisphere::Northern, july + 0.5));
+    assert!(north.0 > 15.0);
+    assert!(south.0 < 0.0);
+    assert!(north.1 > south.1);
   }
 }
@@ -0,0 +1,481 @@
+use legion::*;
+use legion::world::SubWorld;
+use legion::systems::CommandBuffer;
+use rand::Rng;
+use serde::{ Serialize, Deserialize };
+use std::collections::HashMap;
+use super::timer::Timers;
+use super::settings::Settings;
+use super::climate::{ Weather, Wind };
+use super::movements::Position;
+use super::events::{ Events, Event };
+use super::mortality::{ DeathCause, kill_tree, probability_for_period };
+use super::phenology::{ year_days, DAYS_IN_YEAR };
+use super::random::SimRng;
+use super::spatial::{ SpatialIndex, distance_2d };
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, Offspring, min };
+
+static PESTS_CONFIG: &str = include_str!("../config/pests.yaml");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PathogenId(pub u16);
+
+// Pest or disease, rates are per day of a real year
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Pathogen {
+  pub name: String,
+  pub introduction_rate: f32,
+  pub transmission_rate: f32,
+  pub spread_radius: f32,
+  pub wind_spread: f32,
+  pub progression_rate: f32,
+  pub growth_reduction: f32,
+  pub offspring_reduction: f32,
+  pub lethality: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PestsConfig {
+  pathogens: Vec<Pathogen>
+}
+
+pub struct Pathogens(Vec<Pathogen>);
+
+impl Pathogens {
+  pub fn load() -> Self {
+    let config: PestsConfig = serde_yaml::from_str(PESTS_CONFIG).unwrap();
+    Pathogens(config.pathogens)
+  }
+
+  pub fn get(&self, id: PathogenId) -> &Pathogen {
+    &self.0[id.0 as usize]
+  }
+
+  pub fn find(&self, name: &str) -> Option<PathogenId> {
+    self.0.iter()
+      .position(|pathogen| pathogen.name == name)
+      .map(|ix| PathogenId(ix as u16))
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (PathogenId, &Pathogen)> {
+    self.0.iter().enumerate().map(|(ix, pathogen)| (PathogenId(ix as u16), pathogen))
+  }
+}
+
+// Tree is a host of the pathogen. Severity grows from 0.0 to 1.0, when the
+// tree either dies or recovers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Infection {
+  pub pathogen: PathogenId,
+  pub severity: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+  Dies,
+  Recovers,
+}
+
+pub fn susceptibility(tree_type: &TreeType, pathogen: &Pathogen) -> f32 {
+  tree_type.susceptibility.get(&pathogen.name).copied().unwrap_or(0.0)
+}
+
+// Distance the pathogen reaches from source towards target. Wind carries it
+// further downwind.
+pub fn reach(pathogen: &Pathogen, wind: &Wind, source: &Position, target: &Position) -> f32 {
+  let offset = target.0 - source.0;
+  let distance = distance_2d(&source.0, &target.0);
+  let downwind = if distance > 0.0 {
+    (offset.x * wind.direction.cos() + offset.y * wind.direction.sin()) / distance
+  } else {
+    0.0
+  };
+  pathogen.spread_radius + pathogen.wind_spread * wind.speed * downwind.max(0.0)
+}
+
+pub fn advance_infection<R: Rng>(
+  infection: &mut Infection,
+  pathogen: &Pathogen,
+  susceptibility: f32,
+  days: f32,
+  rng: &mut R
+) -> Option<Outcome> {
+  infection.severity = min(1.0, infection.severity + pathogen.progression_rate * susceptibility * days);
+  if infection.severity < 1.0 {
+    None
+  } else if rng.gen_bool(pathogen.lethality.clamp(0.0, 1.0) as f64) {
+    Some(Outcome::Dies)
+  } else {
+    Some(Outcome::Recovers)
+  }
+}
+
+// Fruits lost during short ticks add up to whole ones
+pub fn lose_offspring(offspring: &mut Offspring, pathogen: &Pathogen, severity: f32, days: f32) {
+  let kept = (1.0 - pathogen.offspring_reduction * severity).max(0.0).powf(days);
+  let lost = offspring.amount as f32 * (1.0 - kept) + offspring.lost;
+  let whole = min(lost.floor(), offspring.amount as f32);
+  offspring.amount -= whole as u16;
+  offspring.lost = lost - whole;
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Outbreak {
+  pub infected: usize, // hosts infected at the end of the last tick
+  pub new_infections: usize, // during the last tick
+  pub total_infections: usize,
+  pub peak_infected: usize,
+  pub deaths: usize,
+  pub recoveries: usize,
+}
+
+#[derive(Default)]
+pub struct OutbreakMetrics(HashMap<PathogenId, Outbreak>);
+
+impl OutbreakMetrics {
+  pub fn get(&self, pathogen: PathogenId) -> Outbreak {
+    self.0.get(&pathogen).cloned().unwrap_or_default()
+  }
+}
+
+// Infections come from outside at introduction rate and from infected
+// neighbours within reach of the pathogen
+#[system]
+#[read_component(Position)]
+#[read_component(SpeciesId)]
+#[read_component(TreeProperties)]
+#[read_component(Infection)]
+#[allow(clippy::too_many_arguments)]
+pub fn spread_disease(
+  world: &mut SubWorld,
+  commands: &mut CommandBuffer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] weather: &Weather,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] pathogens: &Pathogens,
+  #[resource] trees: &SpatialIndex<TreeProperties>,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let days = year_days(time.long.elapsed_seconds, settings);
+  let mut new: HashMap<Entity, PathogenId> = HashMap::new();
+
+  let mut query = <(Entity, &Position, &Infection)>::query().filter(component::<TreeProperties>());
+  let sources: Vec<(Entity, Position, PathogenId)> = query.iter(world)
+    .map(|(entity, position, infection)| (*entity, *position, infection.pathogen))
+    .collect();
+
+  for (source, position, id) in &sources {
+    let pathogen = pathogens.get(*id);
+    let max_reach = pathogen.spread_radius + pathogen.wind_spread * weather.wind.speed;
+    for target in trees.within_radius(position, max_reach) {
+      if target == *source || new.contains_key(&target) {
+        continue;
+      }
+      let entry = match world.entry_ref(target) {
+        Ok(entry) => entry,
+        Err(_) => continue
+      };
+      if entry.get_component::<Infection>().is_ok() {
+        continue;
+      }
+      let (target_position, species) = match (entry.get_component::<Position>(), entry.get_component::<SpeciesId>()) {
+        (Ok(target_position), Ok(species)) => (*target_position, *species),
+        _ => continue
+      };
+      if distance_2d(&position.0, &target_position.0) > reach(pathogen, &weather.wind, position, &target_position) {
+        continue;
+      }
+      let daily = pathogen.transmission_rate * susceptibility(registry.get(species), pathogen);
+      if daily > 0.0 && rng.gen_bool(probability_for_period(daily.min(1.0), days)) {
+        new.insert(target, *id);
+      }
+    }
+  }
+
+  let mut query = <(Entity, &SpeciesId)>::query().filter(component::<TreeProperties>() & !component::<Infection>());
+  for (entity, species) in query.iter(world) {
+    if new.contains_key(entity) {
+      continue;
+    }
+    let tree_type = registry.get(*species);
+    for (id, pathogen) in pathogens.iter() {
+      let yearly = pathogen.introduction_rate * susceptibility(tree_type, pathogen);
+      if yearly > 0.0 && rng.gen_bool(probability_for_period(yearly.min(1.0), days / DAYS_IN_YEAR)) {
+        new.insert(*entity, id);
+        break;
+      }
+    }
+  }
+
+  for (entity, pathogen) in new {
+    commands.add_component(entity, Infection { pathogen, severity: 0.0 });
+    events.emit(Event::TreeInfected { entity, pathogen });
+  }
+}
+
+// Infection slows host growth, destroys developing fruits and in the end
+// either kills the host or goes away
+#[system(for_each)]
+#[allow(clippy::too_many_arguments)]
+pub fn progress_disease(
+  entity: &Entity,
+  species: &SpeciesId,
+  properties: &mut TreeProperties,
+  infection: &mut Infection,
+  offspring: Option<&mut Offspring>,
+  command_buffer: &mut CommandBuffer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] pathogens: &Pathogens,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let days = year_days(time.long.elapsed_seconds, settings);
+  let tree_type = registry.get(*species);
+  let pathogen = pathogens.get(infection.pathogen);
+  let outcome = advance_infection(infection, pathogen, susceptibility(tree_type, pathogen), days, rng);
+  properties.vigour = 1.0 - pathogen.growth_reduction * infection.severity;
+  if let Some(offspring) = offspring {
+    lose_offspring(offspring, pathogen, infection.severity, days);
+  }
+
+  match outcome {
+    Some(Outcome::Dies) => {
+      kill_tree(entity, properties, DeathCause::Disease(infection.pathogen), command_buffer, settings, events);
+    },
+    Some(Outcome::Recovers) => {
+      properties.vigour = 1.0;
+      command_buffer.remove_component::<Infection>(*entity);
+      events.emit(Event::TreeRecovered { entity: *entity, pathogen: infection.pathogen });
+    },
+    None => {}
+  }
+}
+
+// Should run after command buffers are flushed, so new infections are counted
+#[system]
+#[read_component(Infection)]
+#[read_component(TreeProperties)]
+pub fn update_outbreak_metrics(
+  world: &mut SubWorld,
+  #[resource] metrics: &mut OutbreakMetrics,
+  #[resource] events: &Events,
+) {
+  for outbreak in metrics.0.values_mut() {
+    outbreak.infected = 0;
+    outbreak.new_infections = 0;
+  }
+  let mut query = <&Infection>::query().filter(component::<TreeProperties>());
+  for infection in query.iter(world) {
+    metrics.0.entry(infection.pathogen).or_default().infected += 1;
+  }
+  for event in events.iter() {
+    match event {
+      Event::TreeInfected { pathogen, .. } => {
+        let outbreak = metrics.0.entry(*pathogen).or_default();
+        outbreak.new_infections += 1;
+        outbreak.total_infections += 1;
+      },
+      Event::TreeRecovered { pathogen, .. } => {
+        metrics.0.entry(*pathogen).or_default().recoveries += 1;
+      },
+      Event::EntityDied { cause: DeathCause::Disease(pathogen), .. } => {
+        metrics.0.entry(*pathogen).or_default().deaths += 1;
+      },
+      _ => {}
+    }
+  }
+  for outbreak in metrics.0.values_mut() {
+    outbreak.peak_infected = outbreak.peak_infected.max(outbreak.infected);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use num::traits::Zero;
+  use super::super::movements::Vector3;
+  use super::super::events::clear_events_system;
+  use super::super::tree::place_tree_test;
+  use super::super::mortality::{ Deadwood, mortality_system };
+
+  fn point(x: f32, y: f32) -> Position {
+    Position(Vector3::new(x, y, 0.0))
+  }
+
+  #[test]
+  fn susceptibility_is_configured_per_species() {
+    let registry = SpeciesRegistry::load();
+    let pathogens = Pathogens::load();
+    let wilt = pathogens.get(pathogens.find("oak_wilt").unwrap());
+    assert_eq!(susceptibility(registry.get(registry.find("oak").unwrap()), wilt), 1.0);
+    assert_eq!(susceptibility(registry.get(registry.find("hazel").unwrap()), wilt), 0.0);
+  }
+
+  #[test]
+  fn wind_carries_pathogen_downwind() {
+    let pathogens = Pathogens::load();
+    let mildew = pathogens.get(pathogens.find("powdery_mildew").unwrap());
+    let wind = Wind { direction: 0.0, speed: 2.0 };
+    let downwind = reach(mildew, &wind, &Position::zero(), &point(5.0, 0.0));
+    let upwind = reach(mildew, &wind, &Position::zero(), &point(-5.0, 0.0));
+    assert_eq!(upwind, mildew.spread_radius);
+    assert!(downwind > mildew.spread_radius + 1.0);
+  }
+
+  #[test]
+  fn infection_ends_by_death_or_recovery() {
+    let pathogens = Pathogens::load();
+    let mut rng = SimRng::seeded(1);
+    let lethal = Pathogen { lethality: 1.0, ..pathogens.get(PathogenId(0)).clone() };
+    let harmless = Pathogen { lethality: 0.0, ..lethal.clone() };
+    let mut infection = Infection { pathogen: PathogenId(0), severity: 0.0 };
+    assert_eq!(advance_infection(&mut infection, &lethal, 1.0, 1.0, &mut rng), None);
+    assert!(infection.severity > 0.0);
+    assert_eq!(advance_infection(&mut infection.clone(), &lethal, 1.0, 1000.0, &mut rng), Some(Outcome::Dies));
+    assert_eq!(advance_infection(&mut infection.clone(), &harmless, 1.0, 1000.0, &mut rng), Some(Outcome::Recovers));
+  }
+
+  #[test]
+  fn disease_spreads_to_susceptible_neighbours() {
+    let registry = SpeciesRegistry::load();
+    let pathogens = Pathogens::load();
+    let wilt = pathogens.find("oak_wilt").unwrap();
+    let settings = Settings::default();
+    let mut world = World::default();
+    let mut index = SpatialIndex::<TreeProperties>::new(5.0);
+    let mut plant = |name, position| {
+      let entity = place_tree_test(&mut world, &registry, name, position, 0.0, 5.0).unwrap();
+      index.insert(entity, &position);
+      entity
+    };
+    let source = plant("oak", Position::zero());
+    let neighbour = plant("oak", point(3.0, 0.0));
+    let hazel = plant("hazel", point(0.0, 3.0));
+    let distant = plant("oak", point(100.0, 0.0));
+    world.entry(source).unwrap().add_component(Infection { pathogen: wilt, severity: 0.5 });
+
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = 100.0 * settings.seconds_in_year() / DAYS_IN_YEAR;
+    let mut resources = Resources::default();
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(Weather::prepare("moderate"));
+    resources.insert(registry);
+    resources.insert(pathogens);
+    resources.insert(index);
+    resources.insert(SimRng::seeded(1));
+    resources.insert(OutbreakMetrics::default());
+    resources.insert(Events::default());
+    let mut schedule = Schedule::builder()
+      .add_system(spread_disease_system())
+      .flush()
+      .add_system(update_outbreak_metrics_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let infected = |entity| world.entry_ref(entity).unwrap().get_component::<Infection>().is_ok();
+    assert!(infected(neighbour));
+    assert!(!infected(hazel));
+    assert!(!infected(distant));
+    let outbreak = resources.get::<OutbreakMetrics>().unwrap().get(wilt);
+    assert_eq!(outbreak.new_infections, 1);
+    assert_eq!(outbreak.infected, 2);
+    assert_eq!(outbreak.peak_infected, 2);
+  }
+
+  #[test]
+  fn tree_dies_once_of_disease_and_old_age() {
+    let registry = SpeciesRegistry::load();
+    let mut pathogens = Pathogens::load();
+    let wilt = pathogens.find("oak_wilt").unwrap();
+    pathogens.0[wilt.0 as usize].lethality = 1.0;
+    let settings = Settings::default();
+    let max_age = registry.get(registry.find("oak").unwrap()).max_age_years;
+    let mut world = World::default();
+    let age = (max_age + 1.0) * settings.seconds_in_year();
+    let tree = place_tree_test(&mut world, &registry, "oak", Position::zero(), age, 5.0).unwrap();
+    world.entry(tree).unwrap().add_component(Infection { pathogen: wilt, severity: 1.0 });
+
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = settings.day_duration;
+    let mut resources = Resources::default();
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(registry);
+    resources.insert(pathogens);
+    resources.insert(Weather::prepare("moderate"));
+    resources.insert(SimRng::seeded(1));
+    resources.insert(Events::default());
+    let mut schedule = Schedule::builder()
+      .add_system(mortality_system())
+      .add_system(progress_disease_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let events = resources.get::<Events>().unwrap();
+    let deaths: Vec<&Event> = events.iter().filter(|event| matches!(event, Event::EntityDied { .. })).collect();
+    assert_eq!(deaths, vec!(&Event::EntityDied { entity: tree, cause: DeathCause::OldAge }));
+    let entry = world.entry_ref(tree).unwrap();
+    assert!(entry.get_component::<TreeProperties>().is_err());
+    assert!(entry.get_component::<Deadwood>().is_ok());
+  }
+
+  #[test]
+  fn offspring_loss_adds_up_over_short_ticks() {
+    let pathogens = Pathogens::load();
+    let pathogen = pathogens.get(pathogens.find("oak_wilt").unwrap());
+    let mut offspring = Offspring { amount: 100, maturity: 0.0, pollen: Vec::new(), lost: 0.0 };
+    for _ in 0..100 {
+      lose_offspring(&mut offspring, pathogen, 0.5, 0.01);
+    }
+    let expected = 100.0 * (1.0 - pathogen.offspring_reduction * 0.5).powf(1.0);
+    assert!((offspring.amount as f32 - offspring.lost - expected).abs() < 1.0);
+  }
+
+  #[test]
+  fn lethal_disease_kills_host() {
+    let registry = SpeciesRegistry::load();
+    let pathogens = Pathogens::load();
+    let wilt = pathogens.find("oak_wilt").unwrap();
+    let settings = Settings::default();
+    let mut world = World::default();
+    let tree = place_tree_test(&mut world, &registry, "oak", Position::zero(), 0.0, 5.0).unwrap();
+    world.entry(tree).unwrap().add_component(Infection { pathogen: wilt, severity: 0.5 });
+    world.entry(tree).unwrap().add_component(Offspring { amount: 100, maturity: 0.0, pollen: Vec::new(), lost: 0.0 });
+
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = 10.0 * settings.seconds_in_year() / DAYS_IN_YEAR;
+    let mut resources = Resources::default();
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(registry);
+    resources.insert(pathogens);
+    resources.insert(SimRng::seeded(1));
+    resources.insert(OutbreakMetrics::default());
+    resources.insert(Events::default());
+    let mut schedule = Schedule::builder()
+      .add_system(clear_events_system())
+      .add_system(progress_disease_system())
+      .flush()
+      .add_system(update_outbreak_metrics_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let entry = world.entry_ref(tree).unwrap();
+    assert!(entry.get_component::<TreeProperties>().unwrap().vigour < 1.0);
+    assert!(entry.get_component::<Offspring>().unwrap().amount < 100);
+
+    for _ in 0..100 {
+      schedule.execute(&mut world, &mut resources);
+    }
+    let outbreak = resources.get::<OutbreakMetrics>().unwrap().get(wilt);
+    assert_eq!(outbreak.deaths + outbreak.recoveries, 1);
+    let entry = world.entry_ref(tree).unwrap();
+    assert!(entry.get_component::<Infection>().is_err());
+  }
+}
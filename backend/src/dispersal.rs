@@ -0,0 +1,181 @@
+use rand::Rng;
+use serde::{ Serialize, Deserialize };
+use std::f32::consts::PI;
+use super::climate::Wind;
+use super::map::Map;
+use super::movements::{ Position, Vector3 };
+
+// How many times we try to place seed inside of the map before it is lost
+const MAX_DISPERSAL_ATTEMPTS: usize = 10;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kernel", rename_all = "snake_case")]
+pub enum DispersalKernel {
+  // seeds fall uniformly under the crown
+  Gravity,
+  // 2D exponential kernel, distance is Gamma(2, mean_distance / 2) distributed
+  Exponential {
+    mean_distance: f32
+  },
+  // 2Dt kernel by Clark et al, P(distance > r) = (1 + r^2 / scale)^-shape
+  StudentT {
+    scale: f32,
+    shape: f32
+  },
+  // exponential kernel stretched by wind speed and turned to wind direction
+  Wind {
+    mean_distance: f32,
+    distance_per_wind_speed: f32,
+    angular_spread: f32
+  },
+  // most seeds are dropped close, but some are carried far away by animals
+  Animal {
+    mean_distance: f32,
+    long_distance_share: f32,
+    long_distance_mean: f32
+  },
+}
+
+fn exponential_distance<R: Rng>(mean_distance: f32, rng: &mut R) -> f32 {
+  let u1: f32 = 1.0 - rng.gen::<f32>();
+  let u2: f32 = 1.0 - rng.gen::<f32>();
+  -mean_distance / 2.0 * (u1.ln() + u2.ln())
+}
+
+fn uniform_angle<R: Rng>(rng: &mut R) -> f32 {
+  rng.gen_range(0.0..2.0 * PI)
+}
+
+impl DispersalKernel {
+  // Distance and direction of the seed from the parent tree
+  pub fn sample<R: Rng>(&self, crown_radius: f32, wind: &Wind, rng: &mut R) -> (f32, f32) {
+    match self {
+      DispersalKernel::Gravity => {
+        (crown_radius * rng.gen::<f32>().sqrt(), uniform_angle(rng))
+      },
+      DispersalKernel::Exponential { mean_distance } => {
+        (exponential_distance(*mean_distance, rng), uniform_angle(rng))
+      },
+      DispersalKernel::StudentT { scale, shape } => {
+        let u: f32 = 1.0 - rng.gen::<f32>();
+        ((scale * (u.powf(-1.0 / shape) - 1.0)).sqrt(), uniform_angle(rng))
+      },
+      DispersalKernel::Wind { mean_distance, distance_per_wind_speed, angular_spread } => {
+        let mean = mean_distance + distance_per_wind_speed * wind.speed;
+        let deviation = (rng.gen::<f32>() - rng.gen::<f32>()) * angular_spread;
+        (exponential_distance(mean, rng), wind.direction + deviation)
+      },
+      DispersalKernel::Animal { mean_distance, long_distance_share, long_distance_mean } => {
+        let mean = if rng.gen::<f32>() < *long_distance_share { long_distance_mean } else { mean_distance };
+        (exponential_distance(*mean, rng), uniform_angle(rng))
+      },
+    }
+  }
+}
+
+// Position of a seed dispersed from origin, or None when it leaves the map
+pub fn disperse<R: Rng>(
+  kernel: &DispersalKernel,
+  origin: &Position,
+  crown_radius: f32,
+  wind: &Wind,
+  map: &Map,
+  rng: &mut R
+) -> Option<Position> {
+  (0..MAX_DISPERSAL_ATTEMPTS)
+    .map(|_| {
+      let (distance, angle) = kernel.sample(crown_radius, wind, rng);
+      *origin + Position(Vector3::new(distance * angle.cos(), distance * angle.sin(), 0.0))
+    })
+    .find(|position| map.contains(position))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::random::SimRng;
+  use num::traits::Zero;
+
+  const SAMPLES: usize = 20_000;
+
+  fn calm() -> Wind {
+    Wind { direction: 0.0, speed: 0.0 }
+  }
+
+  fn distances(kernel: &DispersalKernel, crown_radius: f32, wind: &Wind) -> Vec<f32> {
+    let mut rng = SimRng::seeded(42);
+    let mut distances: Vec<f32> = (0..SAMPLES).map(|_| kernel.sample(crown_radius, wind, &mut rng).0).collect();
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    distances
+  }
+
+  fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+  }
+
+  fn close_to(value: f32, expected: f32, tolerance: f32) -> bool {
+    (value - expected).abs() <= expected * tolerance
+  }
+
+  #[test]
+  fn gravity_drops_seeds_under_crown() {
+    let distances = distances(&DispersalKernel::Gravity, 3.0, &calm());
+    assert!(distances.iter().all(|d| *d <= 3.0));
+    // uniform disk has mean distance of 2/3 radius
+    assert!(close_to(mean(&distances), 2.0, 0.03));
+  }
+
+  #[test]
+  fn exponential_kernel_has_configured_mean() {
+    let distances = distances(&DispersalKernel::Exponential { mean_distance: 5.0 }, 1.0, &calm());
+    assert!(close_to(mean(&distances), 5.0, 0.03));
+  }
+
+  #[test]
+  fn student_t_kernel_matches_analytical_quantiles() {
+    let (scale, shape) = (4.0_f32, 1.5_f32);
+    let distances = distances(&DispersalKernel::StudentT { scale, shape }, 1.0, &calm());
+    for &q in &[0.25_f32, 0.5, 0.9] {
+      let expected = (scale * ((1.0 - q).powf(-1.0 / shape) - 1.0)).sqrt();
+      let empirical = distances[(q * SAMPLES as f32) as usize];
+      assert!(close_to(empirical, expected, 0.05), "q{}: {} vs {}", q, empirical, expected);
+    }
+  }
+
+  #[test]
+  fn wind_carries_seeds_downwind() {
+    let kernel = DispersalKernel::Wind { mean_distance: 2.0, distance_per_wind_speed: 1.0, angular_spread: 0.5 };
+    let wind = Wind { direction: PI / 2.0, speed: 4.0 };
+    let mut rng = SimRng::seeded(42);
+    let shift = (0..SAMPLES)
+      .map(|_| kernel.sample(1.0, &wind, &mut rng))
+      .fold(Vector3::zeros(), |acc, (d, angle)| acc + Vector3::new(d * angle.cos(), d * angle.sin(), 0.0))
+      / SAMPLES as f32;
+    assert!(shift.y > 4.0);
+    assert!(shift.x.abs() < 0.2);
+    assert!(close_to(mean(&distances(&kernel, 1.0, &wind)), 6.0, 0.03));
+  }
+
+  #[test]
+  fn animals_carry_part_of_seeds_far_away() {
+    let kernel = DispersalKernel::Animal { mean_distance: 1.0, long_distance_share: 0.1, long_distance_mean: 100.0 };
+    let distances = distances(&kernel, 1.0, &calm());
+    assert!(close_to(mean(&distances), 0.9 * 1.0 + 0.1 * 100.0, 0.05));
+    let far = distances.iter().filter(|d| **d > 20.0).count() as f32 / SAMPLES as f32;
+    assert!(far > 0.08 && far < 0.1);
+  }
+
+  #[test]
+  fn seeds_stay_inside_of_map() {
+    let map = Map::test_square(10.0, 10.0);
+    let kernel = DispersalKernel::Exponential { mean_distance: 4.0 };
+    let mut rng = SimRng::seeded(42);
+    let corner = Position(Vector3::new(4.5, 4.5, 0.0));
+    let placed: Vec<Position> = (0..1000)
+      .filter_map(|_| disperse(&kernel, &corner, 1.0, &calm(), &map, &mut rng))
+      .collect();
+    assert!(placed.len() > 900);
+    assert!(placed.iter().all(|p| map.contains(p)));
+    assert!(disperse(&kernel, &Position::zero(), 1.0, &calm(), &Map::test_square(0.0, 0.0), &mut rng).is_none());
+  }
+}
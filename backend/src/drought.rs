@@ -0,0 +1,158 @@
+use legion::*;
+use serde::{ Serialize, Deserialize };
+use super::timer::Timers;
+use super::settings::Settings;
+use super::climate::Weather;
+use super::map::Map;
+use super::movements::Position;
+use super::phenology::year_days;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, TreeState, clamp, min };
+
+static WATER_CONFIG: &str = include_str!("../config/water.yaml");
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WaterConfig {
+  pub reference_precipitation: f32,
+  pub buffer_days: f32,
+}
+
+impl WaterConfig {
+  pub fn load() -> Self {
+    serde_yaml::from_str(WATER_CONFIG).unwrap()
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DroughtTolerance {
+  pub min_water: f32,
+  pub leaf_fall_days: f32,
+  pub survival_days: f32,
+}
+
+// Water available at the place now, moist ground needs less rain to stay wet
+pub fn water_supply(ground_moisture: f32, precipitation: f32, config: &WaterConfig) -> f32 {
+  clamp(ground_moisture * precipitation / config.reference_precipitation, 0.0, 1.0)
+}
+
+// Water status follows supply with a delay, as ground dries out slowly.
+// Days below min_water are counted as drought stress, except during
+// dormancy when the tree does not transpire.
+pub fn update_water_status(
+  properties: &mut TreeProperties,
+  tree_type: &TreeType,
+  supply: f32,
+  days: f32,
+  config: &WaterConfig
+) {
+  properties.water += (supply - properties.water) * (1.0 - (-days / config.buffer_days).exp());
+  if properties.water >= tree_type.drought.min_water {
+    properties.drought_days = 0.0;
+  } else if properties.state != TreeState::Sleep {
+    properties.drought_days += days;
+  }
+}
+
+// Part of growth and fruits the tree manages with its water
+pub fn water_limitation(properties: &TreeProperties, tree_type: &TreeType) -> f32 {
+  let min_water = tree_type.drought.min_water;
+  if min_water > 0.0 {
+    min(1.0, properties.water / min_water)
+  } else {
+    1.0
+  }
+}
+
+// Stressed tree drops leaves before the season ends
+pub fn early_leaf_fall(properties: &TreeProperties, tree_type: &TreeType, state: TreeState) -> TreeState {
+  match state {
+    TreeState::Blossom | TreeState::Ripening if properties.drought_days > tree_type.drought.leaf_fall_days => TreeState::Falling,
+    state => state
+  }
+}
+
+pub fn dies_of_drought(properties: &TreeProperties, tree_type: &TreeType) -> bool {
+  properties.drought_days > tree_type.drought.survival_days
+}
+
+#[system(for_each)]
+#[allow(clippy::too_many_arguments)]
+pub fn update_water(
+  position: &Position,
+  species: &SpeciesId,
+  properties: &mut TreeProperties,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] weather: &Weather,
+  #[resource] map: &Map,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] config: &WaterConfig,
+) {
+  let ground_moisture = map.ground_type(position).map_or(0.0, |ground| ground.moisture());
+  let supply = water_supply(ground_moisture, weather.current_precipitation, config);
+  let days = year_days(time.long.elapsed_seconds, settings);
+  update_water_status(properties, registry.get(*species), supply, days, config);
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn oak() -> TreeType {
+    let registry = SpeciesRegistry::load();
+    registry.get(registry.find("oak").unwrap()).clone()
+  }
+
+  #[test]
+  fn rain_on_moist_ground_keeps_trees_wet() {
+    let config = WaterConfig::load();
+    assert_eq!(water_supply(1.0, config.reference_precipitation, &config), 1.0);
+    assert!(water_supply(0.2, config.reference_precipitation, &config) < water_supply(0.7, config.reference_precipitation, &config));
+    assert_eq!(water_supply(0.7, 0.0, &config), 0.0);
+    assert_eq!(water_supply(0.7, 1000.0, &config), 1.0);
+  }
+
+  #[test]
+  fn ground_dries_out_slowly() {
+    let config = WaterConfig::load();
+    let oak = oak();
+    let mut tree = TreeProperties { state: TreeState::Blossom, ..TreeProperties::new(0.0, 1.0) };
+    update_water_status(&mut tree, &oak, 0.0, 1.0, &config);
+    assert!(tree.water > 0.9);
+    assert_eq!(tree.drought_days, 0.0);
+    update_water_status(&mut tree, &oak, 0.0, 10.0 * config.buffer_days, &config);
+    assert!(tree.water < oak.drought.min_water);
+    assert_eq!(tree.drought_days, 10.0 * config.buffer_days);
+    update_water_status(&mut tree, &oak, 1.0, 10.0 * config.buffer_days, &config);
+    assert_eq!(tree.drought_days, 0.0);
+  }
+
+  #[test]
+  fn drought_limits_growth_and_sheds_leaves() {
+    let oak = oak();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    assert_eq!(water_limitation(&tree, &oak), 1.0);
+    tree.water = oak.drought.min_water / 2.0;
+    assert!((water_limitation(&tree, &oak) - 0.5).abs() < 1e-6);
+
+    tree.drought_days = oak.drought.leaf_fall_days + 1.0;
+    assert_eq!(early_leaf_fall(&tree, &oak, TreeState::Ripening), TreeState::Falling);
+    assert_eq!(early_leaf_fall(&tree, &oak, TreeState::Sleep), TreeState::Sleep);
+    assert!(!dies_of_drought(&tree, &oak));
+    tree.drought_days = oak.drought.survival_days + 1.0;
+    assert!(dies_of_drought(&tree, &oak));
+  }
+
+  #[test]
+  fn dormant_trees_are_not_stressed() {
+    let config = WaterConfig::load();
+    let oak = oak();
+    let mut tree = TreeProperties { state: TreeState::Sleep, ..TreeProperties::new(0.0, 1.0) };
+    update_water_status(&mut tree, &oak, 0.0, 10.0 * config.buffer_days, &config);
+    assert!(tree.water < oak.drought.min_water);
+    assert_eq!(tree.drought_days, 0.0);
+    tree.state = TreeState::Blossom;
+    update_water_status(&mut tree, &oak, 0.0, 1.0, &config);
+    assert_eq!(tree.drought_days, 1.0);
+  }
+}
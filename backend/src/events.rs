@@ -0,0 +1,168 @@
+use legion::*;
+use std::collections::BTreeMap;
+use super::tree::TreeState;
+use super::timer::Season;
+use super::movements::Position;
+use super::mortality::DeathCause;
+use super::disease::PathogenId;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+  TreeStateChanged {
+    entity: Entity,
+    from: TreeState,
+    to: TreeState
+  },
+  OffspringCreated {
+    entity: Entity,
+    amount: u16
+  },
+  TreeSeeded {
+    parent: Option<Entity>,
+    entity: Entity,
+    position: Position
+  },
+  FruitDropped {
+    parent: Entity,
+    entity: Option<Entity>,
+    position: Position
+  },
+  PlantSprouted {
+    parent: Entity,
+    entity: Entity,
+    position: Position
+  },
+  FruitGerminated {
+    fruit: Entity,
+    entity: Entity
+  },
+  TreeInfected {
+    entity: Entity,
+    pathogen: PathogenId
+  },
+  TreeRecovered {
+    entity: Entity,
+    pathogen: PathogenId
+  },
+  FireIgnited {
+    position: Position
+  },
+  EntityDied {
+    entity: Entity,
+    cause: DeathCause
+  },
+  SeasonChanged {
+    from: Season,
+    to: Season
+  },
+}
+
+impl Event {
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Event::TreeStateChanged { .. } => "tree_state_changed",
+      Event::OffspringCreated { .. } => "offspring_created",
+      Event::TreeSeeded { .. } => "tree_seeded",
+      Event::FruitDropped { .. } => "fruit_dropped",
+      Event::PlantSprouted { .. } => "plant_sprouted",
+      Event::FruitGerminated { .. } => "fruit_germinated",
+      Event::TreeInfected { .. } => "tree_infected",
+      Event::TreeRecovered { .. } => "tree_recovered",
+      Event::FireIgnited { .. } => "fire_ignited",
+      Event::EntityDied { .. } => "entity_died",
+      Event::SeasonChanged { .. } => "season_changed",
+    }
+  }
+}
+
+// Events emitted during the current slow tick. The buffer is cleared at the
+// start of every tick, so consumers should run at the end of the schedule.
+#[derive(Default)]
+pub struct Events(Vec<Event>);
+
+impl Events {
+  pub fn emit(&mut self, event: Event) {
+    self.0.push(event);
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Event> {
+    self.0.iter()
+  }
+
+  pub fn clear(&mut self) {
+    self.0.clear();
+  }
+
+  // Number of events of every kind, ordered by kind
+  pub fn counts(&self) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for event in self.iter() {
+      *counts.entry(event.kind()).or_insert(0) += 1;
+    }
+    counts
+  }
+}
+
+#[system]
+pub fn clear_events(#[resource] events: &mut Events) {
+  events.clear();
+}
+
+// Logs how many events of every kind happened during the tick, single events
+// are too many to follow on a stand of any size
+#[system]
+pub fn log_events(#[resource] events: &Events) {
+  let counts = events.counts();
+  if !counts.is_empty() {
+    let summary: Vec<String> = counts.iter().map(|(kind, count)| format!("{} {}", kind, count)).collect();
+    println!("events: {}", summary.join(", "));
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn events_are_kept_until_cleared() {
+    let mut events = Events::default();
+    events.emit(Event::SeasonChanged { from: Season::Spring, to: Season::Summer });
+    events.emit(Event::SeasonChanged { from: Season::Summer, to: Season::Autumn });
+    assert_eq!(events.iter().count(), 2);
+    assert_eq!(
+      events.iter().next(),
+      Some(&Event::SeasonChanged { from: Season::Spring, to: Season::Summer })
+    );
+
+    events.clear();
+    assert_eq!(events.iter().count(), 0);
+  }
+
+  #[test]
+  fn events_are_counted_by_kind() {
+    let mut events = Events::default();
+    events.emit(Event::SeasonChanged { from: Season::Spring, to: Season::Summer });
+    events.emit(Event::SeasonChanged { from: Season::Summer, to: Season::Autumn });
+    events.emit(Event::FireIgnited { position: Position(Default::default()) });
+    let counts = events.counts();
+    assert_eq!(counts["season_changed"], 2);
+    assert_eq!(counts["fire_ignited"], 1);
+    assert_eq!(counts.len(), 2);
+  }
+
+  #[test]
+  fn events_are_cleared_each_tick() {
+    let mut world = World::default();
+    let mut resources = Resources::default();
+    resources.insert(Events::default());
+    let mut schedule = Schedule::builder()
+      .add_system(clear_events_system())
+      .build();
+
+    resources.get_mut::<Events>().unwrap().emit(
+      Event::SeasonChanged { from: Season::Winter, to: Season::Spring }
+    );
+    schedule.execute(&mut world, &mut resources);
+    assert_eq!(resources.get::<Events>().unwrap().iter().count(), 0);
+  }
+}
@@ -0,0 +1,449 @@
+use legion::*;
+use legion::world::SubWorld;
+use legion::systems::CommandBuffer;
+use rand::Rng;
+use serde::{ Serialize, Deserialize };
+use super::timer::Timers;
+use super::settings::Settings;
+use super::climate::{ Weather, Wind };
+use super::map::Map;
+use super::movements::{ Position, Vector3 };
+use super::raster::Raster;
+use super::events::{ Events, Event };
+use super::mortality::{ DeathCause, kill_tree, probability_for_period };
+use super::phenology::{ year_days, DAYS_IN_YEAR };
+use super::random::SimRng;
+use super::allometry::Dimensions;
+use super::grass::GrassLayer;
+use super::tree::{ TreeProperties, min };
+
+static FIRE_CONFIG: &str = include_str!("../config/fire.yaml");
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FireConfig {
+  pub ignition_rate: f32,
+  pub tree_fuel: f32,
+  pub min_fuel: f32,
+  pub spread_rate: f32,
+  pub wind_factor: f32,
+  pub burn_days: f32,
+  pub crown_fire_fuel: f32,
+  pub scorch_height: f32,
+  pub recovery_days: f32,
+}
+
+impl FireConfig {
+  pub fn load() -> Self {
+    serde_yaml::from_str(FIRE_CONFIG).unwrap()
+  }
+}
+
+// Fire set at the year counted from the start, e.g. a prescribed burn
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlannedIgnition {
+  pub year: f32,
+  pub position: [f32; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FireState {
+  Unburnt,
+  Burning {
+    fuel: f32 // fuel load when the cell caught fire
+  },
+  Burnt,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FireCell {
+  pub fuel: f32, // kilograms per square meter
+  pub moisture: f32, // of the ground
+  pub flammable: bool,
+  pub state: FireState,
+  pub days: f32, // since the current state started
+}
+
+// Fuel grid over the map. Fuel comes from grass and tree crowns, ground
+// which does not burn stops the fire.
+pub struct FireLayer {
+  config: FireConfig,
+  cells: Raster<FireCell>,
+  planned: Vec<PlannedIgnition>,
+  years: f32, // simulated since the start
+  ignitions: Vec<Position>, // waiting for the next tick
+}
+
+impl FireLayer {
+  pub fn covering(map: &Map, cell_size: f32, config: FireConfig) -> Self {
+    let empty = FireCell { fuel: 0.0, moisture: 0.0, flammable: false, state: FireState::Unburnt, days: 0.0 };
+    let mut cells = Raster::covering(map, cell_size, empty);
+    for ix in 0..cells.len() {
+      if let Some(ground) = map.ground_type(&cells.cell_center(ix)) {
+        let cell = cells.cell_mut(ix);
+        cell.moisture = ground.moisture();
+        cell.flammable = ground.flammable();
+      }
+    }
+    FireLayer { config, cells, planned: Vec::new(), years: 0.0, ignitions: Vec::new() }
+  }
+
+  pub fn state_at(&self, position: &Position) -> FireState {
+    self.cells.get(position).map_or(FireState::Unburnt, |cell| cell.state)
+  }
+
+  #[cfg(test)]
+  pub fn fuel_at(&self, position: &Position) -> f32 {
+    self.cells.get(position).map_or(0.0, |cell| cell.fuel)
+  }
+
+  #[cfg(test)]
+  pub fn burning(&self) -> usize {
+    self.cells.iter().filter(|cell| matches!(cell.state, FireState::Burning { .. })).count()
+  }
+
+  // Area of ground burnt or burning in square meters
+  #[cfg(test)]
+  pub fn burnt_area(&self) -> f32 {
+    let area = self.cells.cell_size() * self.cells.cell_size();
+    self.cells.iter().filter(|cell| cell.state != FireState::Unburnt).count() as f32 * area
+  }
+
+  pub fn plan(&mut self, ignitions: &[PlannedIgnition]) {
+    self.planned.extend_from_slice(ignitions);
+  }
+
+  // Sets the place on fire during the next tick, if it can burn then
+  pub fn ignite(&mut self, position: &Position) {
+    self.ignitions.push(*position);
+  }
+
+  // Advances time, queues planned ignitions which became due and takes the
+  // queue
+  fn due(&mut self, elapsed_years: f32) -> Vec<Position> {
+    self.years += elapsed_years;
+    let years = self.years;
+    let planned: Vec<Position> = self.planned.iter()
+      .filter(|ignition| ignition.year <= years)
+      .map(|ignition| Position(Vector3::new(ignition.position[0], ignition.position[1], 0.0)))
+      .collect();
+    self.planned.retain(|ignition| ignition.year > years);
+    for position in &planned {
+      self.ignite(position);
+    }
+    self.ignitions.drain(..).collect()
+  }
+
+  // Sets the cell on fire if it can burn, returns whether it did
+  fn ignite_at(&mut self, position: &Position) -> bool {
+    match self.cells.cell_index(position) {
+      Some(ix) => self.ignite_cell(ix),
+      None => false
+    }
+  }
+
+  fn ignite_cell(&mut self, ix: usize) -> bool {
+    let min_fuel = self.config.min_fuel;
+    let cell = self.cells.cell_mut(ix);
+    if cell.flammable && cell.state == FireState::Unburnt && cell.fuel >= min_fuel {
+      cell.state = FireState::Burning { fuel: cell.fuel };
+      cell.days = 0.0;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn reset_fuel(&mut self, grass: &GrassLayer) {
+    for ix in 0..self.cells.len() {
+      let biomass = grass.biomass_at(&self.cells.cell_center(ix));
+      self.cells.cell_mut(ix).fuel = biomass;
+    }
+  }
+
+  fn add_tree_fuel(&mut self, position: &Position, biomass: f32) {
+    let area = self.cells.cell_size() * self.cells.cell_size();
+    let tree_fuel = self.config.tree_fuel;
+    if let Some(cell) = self.cells.get_mut(position) {
+      cell.fuel += biomass * tree_fuel / area;
+    }
+  }
+
+  // Burning cells burn out and burnt ground recovers. Returns burnt out cells.
+  fn advance(&mut self, days: f32) -> Vec<usize> {
+    let (burn_days, recovery_days) = (self.config.burn_days, self.config.recovery_days);
+    let mut burnt_out = Vec::new();
+    for ix in 0..self.cells.len() {
+      let cell = self.cells.cell_mut(ix);
+      cell.days += days;
+      match cell.state {
+        FireState::Burning { .. } if cell.days >= burn_days => {
+          cell.state = FireState::Burnt;
+          cell.days = 0.0;
+          burnt_out.push(ix);
+        },
+        FireState::Burnt if cell.days >= recovery_days => {
+          cell.state = FireState::Unburnt;
+          cell.days = 0.0;
+        },
+        _ => {}
+      }
+    }
+    burnt_out
+  }
+
+  // Burning cells ignite their neighbours, faster downwind and in dry air
+  fn spread<R: Rng>(&mut self, wind: &Wind, humidity: f32, days: f32, rng: &mut R) -> Vec<Position> {
+    let burning: Vec<usize> = (0..self.cells.len())
+      .filter(|&ix| matches!(self.cells.cell(ix).state, FireState::Burning { .. }))
+      .collect();
+    let mut ignited = Vec::new();
+    for ix in burning {
+      let source = self.cells.cell_center(ix);
+      let neighbours: Vec<usize> = self.cells.neighbours(ix).collect();
+      for target in neighbours {
+        let cell = self.cells.cell(target);
+        if !cell.flammable || cell.state != FireState::Unburnt || cell.fuel < self.config.min_fuel {
+          continue;
+        }
+        let position = self.cells.cell_center(target);
+        let daily = spread_probability(&self.config, cell, &source, &position, wind, humidity);
+        if daily > 0.0 && rng.gen_bool(probability_for_period(daily, days)) && self.ignite_cell(target) {
+          ignited.push(position);
+        }
+      }
+    }
+    ignited
+  }
+}
+
+// Daily probability for fire to move from source to the neighbouring cell
+pub fn spread_probability(
+  config: &FireConfig,
+  cell: &FireCell,
+  source: &Position,
+  target: &Position,
+  wind: &Wind,
+  humidity: f32
+) -> f32 {
+  let offset = target.0 - source.0;
+  let distance = offset.x.hypot(offset.y);
+  let downwind = if distance > 0.0 {
+    (offset.x * wind.direction.cos() + offset.y * wind.direction.sin()) / distance
+  } else {
+    0.0
+  };
+  let dryness = 1.0 - (cell.moisture + humidity) / 2.0;
+  let wind_effect = (1.0 + config.wind_factor * wind.speed * downwind).max(0.0);
+  min(1.0, config.spread_rate * dryness * wind_effect)
+}
+
+// Trees die in crown fires, ground fires kill only the low ones
+pub fn survives_fire(config: &FireConfig, fuel: f32, dimensions: &Dimensions) -> bool {
+  fuel < config.crown_fire_fuel && dimensions.height >= config.scorch_height
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(Dimensions)]
+#[read_component(TreeProperties)]
+#[allow(clippy::too_many_arguments)]
+pub fn update_fire(
+  world: &mut SubWorld,
+  #[resource] fire: &mut FireLayer,
+  #[resource] grass: &mut GrassLayer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] weather: &Weather,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let days = year_days(time.long.elapsed_seconds, settings);
+  fire.reset_fuel(grass);
+  let mut query = <(&Position, &Dimensions)>::query().filter(component::<TreeProperties>());
+  for (position, dimensions) in query.iter(world) {
+    fire.add_tree_fuel(position, dimensions.biomass);
+  }
+
+  for ix in fire.advance(days) {
+    grass.burn(&fire.cells.cell_center(ix));
+  }
+  fire.spread(&weather.wind, weather.humidity, days, rng);
+
+  let ignition = fire.config.ignition_rate.min(1.0);
+  if ignition > 0.0 && rng.gen_bool(probability_for_period(ignition, days / DAYS_IN_YEAR)) {
+    let ix = rng.gen_range(0..fire.cells.len());
+    let position = fire.cells.cell_center(ix);
+    if fire.ignite_cell(ix) {
+      events.emit(Event::FireIgnited { position });
+    }
+  }
+  for position in fire.due(days / DAYS_IN_YEAR) {
+    if fire.ignite_at(&position) {
+      events.emit(Event::FireIgnited { position });
+    }
+  }
+}
+
+#[system(for_each)]
+#[allow(clippy::too_many_arguments)]
+pub fn burn_trees(
+  entity: &Entity,
+  position: &Position,
+  properties: &mut TreeProperties,
+  dimensions: &Dimensions,
+  command_buffer: &mut CommandBuffer,
+  #[resource] fire: &FireLayer,
+  #[resource] settings: &Settings,
+  #[resource] events: &mut Events,
+) {
+  if let FireState::Burning { fuel } = fire.state_at(position) {
+    if !survives_fire(&fire.config, fuel, dimensions) {
+      kill_tree(entity, properties, DeathCause::Fire, command_buffer, settings, events);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use geo::{ Coordinate, Polygon, Rect };
+  use num::traits::Zero;
+  use super::super::map::GroundType;
+  use super::super::movements::Vector3;
+  use super::super::grass::GrassConfig;
+  use super::super::species::SpeciesRegistry;
+  use super::super::tree::place_tree_test;
+
+  fn point(x: f32, y: f32) -> Position {
+    Position(Vector3::new(x, y, 0.0))
+  }
+
+  fn calm() -> Wind {
+    Wind { direction: 0.0, speed: 0.0 }
+  }
+
+  // Layer with grass everywhere except of a river across x = 0, fire always
+  // spreads to neighbouring grass
+  fn with_river() -> (FireLayer, GrassLayer) {
+    let mut map = Map::test_square(20.0, 20.0);
+    let river = Polygon::from(Rect::new(Coordinate { x: -1.0, y: -10.0 }, Coordinate { x: 1.0, y: 10.0 }));
+    map.add_feature(GroundType::Water, river);
+    let mut grass = GrassLayer::covering(&map, 1.0, GrassConfig::load());
+    grass.grow(20.0, 1000.0);
+    let mut fire = FireLayer::covering(&map, 1.0, FireConfig { spread_rate: 10.0, ..FireConfig::load() });
+    fire.reset_fuel(&grass);
+    (fire, grass)
+  }
+
+  #[test]
+  fn wind_drives_fire_downwind() {
+    let config = FireConfig::load();
+    let cell = FireCell { fuel: 1.0, moisture: 0.5, flammable: true, state: FireState::Unburnt, days: 0.0 };
+    let east = Wind { direction: 0.0, speed: 5.0 };
+    let downwind = spread_probability(&config, &cell, &Position::zero(), &point(1.0, 0.0), &east, 0.5);
+    let upwind = spread_probability(&config, &cell, &Position::zero(), &point(-1.0, 0.0), &east, 0.5);
+    let still = spread_probability(&config, &cell, &Position::zero(), &point(1.0, 0.0), &calm(), 0.5);
+    assert!(downwind > still);
+    assert!(upwind < still);
+    let humid = spread_probability(&config, &cell, &Position::zero(), &point(1.0, 0.0), &calm(), 0.9);
+    assert!(humid < still);
+  }
+
+  #[test]
+  fn fire_spreads_until_water_and_ground_recovers() {
+    let (mut fire, mut grass) = with_river();
+    let config = FireConfig::load();
+    let mut rng = SimRng::seeded(1);
+    assert!(!fire.ignite_at(&point(0.0, 0.0)));
+    assert!(fire.ignite_at(&point(-5.0, 0.0)));
+    for _ in 0..50 {
+      for ix in fire.advance(1.0) {
+        grass.burn(&fire.cells.cell_center(ix));
+      }
+      fire.spread(&calm(), 0.0, 1.0, &mut rng);
+    }
+    assert_eq!(fire.state_at(&point(-9.5, 9.5)), FireState::Burnt);
+    assert_eq!(fire.state_at(&point(5.0, 0.0)), FireState::Unburnt);
+    assert_eq!(grass.biomass_at(&point(-5.0, 0.0)), 0.0);
+    assert_eq!(fire.burning(), 0);
+    assert_eq!(fire.burnt_area(), 9.0 * 20.0);
+
+    fire.advance(config.recovery_days);
+    assert_eq!(fire.state_at(&point(-5.0, 0.0)), FireState::Unburnt);
+    fire.reset_fuel(&grass);
+    assert_eq!(fire.fuel_at(&point(-5.0, 0.0)), 0.0);
+    assert!(!fire.ignite_at(&point(-5.0, 0.0)));
+  }
+
+  #[test]
+  fn ground_fire_kills_only_low_trees() {
+    let config = FireConfig::load();
+    let registry = SpeciesRegistry::load();
+    let oak = registry.get(registry.find("oak").unwrap());
+    let sapling = Dimensions::of(&TreeProperties::new(0.0, 0.1), oak);
+    let old = Dimensions::of(&TreeProperties::new(0.0, 10.0), oak);
+    assert!(!survives_fire(&config, config.min_fuel, &sapling));
+    assert!(survives_fire(&config, config.min_fuel, &old));
+    assert!(!survives_fire(&config, config.crown_fire_fuel, &old));
+  }
+
+  #[test]
+  fn burning_cell_kills_trees() {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    let (mut fire, grass) = with_river();
+    let sapling = place_tree_test(&mut world, &registry, "oak", point(-5.0, 0.0), 0.0, 0.1).unwrap();
+    let across = place_tree_test(&mut world, &registry, "oak", point(5.0, 0.0), 0.0, 0.1).unwrap();
+    fire.ignite_at(&point(-5.0, 0.0));
+    let mut resources = Resources::default();
+    resources.insert(fire);
+    resources.insert(grass);
+    resources.insert(Settings::default());
+    resources.insert(Events::default());
+    let mut schedule = Schedule::builder()
+      .add_system(burn_trees_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let alive = |entity| world.entry_ref(entity).unwrap().get_component::<TreeProperties>().is_ok();
+    assert!(!alive(sapling));
+    assert!(alive(across));
+    let events = resources.get::<Events>().unwrap();
+    assert!(events.iter().any(|event| *event == Event::EntityDied { entity: sapling, cause: DeathCause::Fire }));
+  }
+
+  #[test]
+  fn queued_and_planned_fires_are_ignited() {
+    let (mut fire, grass) = with_river();
+    fire.config.ignition_rate = 0.0;
+    fire.plan(&[PlannedIgnition { year: 1.0, position: [-5.0, 0.0] }]);
+    fire.ignite(&point(5.0, 0.0));
+    let settings = Settings::default();
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = 0.5 * settings.seconds_in_year();
+    let mut resources = Resources::default();
+    resources.insert(fire);
+    resources.insert(grass);
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(Weather::prepare("moderate"));
+    resources.insert(SimRng::seeded(1));
+    resources.insert(Events::default());
+    let mut world = World::default();
+    let mut schedule = Schedule::builder()
+      .add_system(update_fire_system())
+      .build();
+
+    schedule.execute(&mut world, &mut resources);
+    {
+      let fire = resources.get::<FireLayer>().unwrap();
+      assert!(matches!(fire.state_at(&point(5.0, 0.0)), FireState::Burning { .. }));
+      assert_eq!(fire.state_at(&point(-5.0, 0.0)), FireState::Unburnt);
+    }
+    resources.get_mut::<Events>().unwrap().clear();
+    schedule.execute(&mut world, &mut resources);
+    assert!(matches!(resources.get::<FireLayer>().unwrap().state_at(&point(-5.0, 0.0)), FireState::Burning { .. }));
+    let events = resources.get::<Events>().unwrap();
+    assert_eq!(events.iter().filter(|event| matches!(event, Event::FireIgnited { .. })).count(), 1);
+  }
+}
@@ -0,0 +1,295 @@
+use legion::*;
+use legion::systems::CommandBuffer;
+use rand::Rng;
+use super::timer::{ Timers, Season };
+use super::settings::Settings;
+use super::climate::Weather;
+use super::map::{ Map, GroundType };
+use super::movements::Position;
+use super::events::{ Events, Event };
+use super::mortality::DeathCause;
+use super::random::SimRng;
+use super::spatial::SpatialIndex;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, place_tree, max };
+use super::genetics::Parents;
+use super::grass::GrassLayer;
+use super::succession::can_germinate;
+use super::dispersal::disperse;
+
+pub struct Fruit {
+  pub species: SpeciesId,
+  pub age: f32,
+  pub nutrition: f32,
+  pub viability: f32, // 1.0 - fresh seed, 0.0 - rotten
+  pub parents: Parents,
+}
+
+impl Fruit {
+  pub fn new(species: SpeciesId, tree_type: &TreeType) -> Self {
+    Fruit {
+      species,
+      age: 0.0,
+      nutrition: tree_type.fruit_nutrition,
+      viability: 1.0,
+      parents: Parents::default()
+    }
+  }
+}
+
+pub fn rot(fruit: &mut Fruit, tree_type: &TreeType, elapsed_seconds: f32, day_duration: f32) {
+  fruit.age += elapsed_seconds;
+  let freshness = max(0.0, 1.0 - fruit.age / (tree_type.fruit_lifetime_days * day_duration));
+  fruit.viability = freshness;
+  fruit.nutrition = tree_type.fruit_nutrition * freshness;
+}
+
+fn germination_factor(ground: Option<GroundType>) -> f32 {
+  match ground {
+    Some(GroundType::Soil) => 1.0,
+    Some(GroundType::Dirt) => 0.7,
+    Some(GroundType::Sand) => 0.3,
+    Some(GroundType::Rocks) => 0.1,
+    _ => 0.0
+  }
+}
+
+// Part of seeds able to root at the place, 0.0 on bare or crowded ground
+pub fn site_suitability(tree_type: &TreeType, ground: Option<GroundType>, neighbours: usize) -> f32 {
+  if neighbours >= tree_type.germination_max_density {
+    0.0
+  } else {
+    germination_factor(ground)
+  }
+}
+
+// Probability for fruit to germinate during elapsed time
+pub fn germination_probability(
+  viability: f32,
+  tree_type: &TreeType,
+  season: Season,
+  ground: Option<GroundType>,
+  neighbours: usize,
+  elapsed_seconds: f32,
+  day_duration: f32
+) -> f64 {
+  if season != Season::Spring {
+    return 0.0;
+  }
+  let daily = tree_type.germination_rate * viability * site_suitability(tree_type, ground, neighbours);
+  let days = elapsed_seconds / day_duration;
+  (1.0 - (1.0 - daily as f64).powf(days as f64)).clamp(0.0, 1.0)
+}
+
+// Probability for fallen fruit to be eaten by animals during elapsed time
+pub fn predation_probability(tree_type: &TreeType, elapsed_seconds: f32, day_duration: f32) -> f64 {
+  let days = elapsed_seconds / day_duration;
+  (1.0 - (1.0 - tree_type.fruit_predation as f64).powf(days as f64)).clamp(0.0, 1.0)
+}
+
+// Removes fruit from the world and returns its nutritional value
+pub fn eat_fruit(entity: &Entity, fruit: &Fruit, command_buffer: &mut CommandBuffer, events: &mut Events) -> f32 {
+  command_buffer.remove(*entity);
+  events.emit(Event::EntityDied { entity: *entity, cause: DeathCause::Eaten });
+  fruit.nutrition
+}
+
+#[system(for_each)]
+#[allow(clippy::too_many_arguments)]
+pub fn fruit_lifecycle(
+  entity: &Entity,
+  fruit: &mut Fruit,
+  position: &Position,
+  command_buffer: &mut CommandBuffer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] weather: &Weather,
+  #[resource] map: &Map,
+  #[resource] grass: &GrassLayer,
+  #[resource] trees: &SpatialIndex<TreeProperties>,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let tree_type = registry.get(fruit.species);
+  rot(fruit, tree_type, time.long.elapsed_seconds, settings.day_duration);
+  if fruit.viability <= 0.0 {
+    command_buffer.remove(*entity);
+    events.emit(Event::EntityDied { entity: *entity, cause: DeathCause::Rotten });
+    return;
+  }
+  if rng.gen_bool(predation_probability(tree_type, time.long.elapsed_seconds, settings.day_duration)) {
+    let cache = if rng.gen::<f32>() < tree_type.fruit_caching {
+      disperse(&tree_type.dispersal, position, 0.0, &weather.wind, map, rng)
+    } else {
+      None
+    };
+    match cache {
+      Some(cache) => command_buffer.add_component(*entity, cache),
+      None => { eat_fruit(entity, fruit, command_buffer, events); }
+    }
+    return;
+  }
+  if !can_germinate(tree_type, grass.light_at(position)) {
+    return;
+  }
+
+  let neighbours = trees.within_radius(position, tree_type.germination_radius).count();
+  let probability = germination_probability(
+    fruit.viability,
+    tree_type,
+    time.current_season,
+    map.ground_type(position),
+    neighbours,
+    time.long.elapsed_seconds,
+    settings.day_duration
+  );
+  if rng.gen_bool(probability) {
+    command_buffer.remove(*entity);
+    let genome = fruit.parents.child(&tree_type.genetics, rng);
+    let tree = place_tree(command_buffer, *position, fruit.species, genome);
+    events.emit(Event::FruitGerminated { fruit: *entity, entity: tree });
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::grass::GrassConfig;
+  use super::super::movements::Vector3;
+  use super::super::tree::TreeConfig;
+
+  fn oak() -> (SpeciesId, TreeType) {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    (oak, registry.get(oak).clone())
+  }
+
+  #[test]
+  fn fruit_rots_over_its_lifetime() {
+    let (species, oak) = oak();
+    let mut fruit = Fruit::new(species, &oak);
+    let lifetime_days = oak.fruit_lifetime_days;
+    rot(&mut fruit, &oak, lifetime_days / 2.0, 1.0);
+    assert_eq!(fruit.viability, 0.5);
+    assert_eq!(fruit.nutrition, oak.fruit_nutrition / 2.0);
+    rot(&mut fruit, &oak, lifetime_days, 1.0);
+    assert_eq!(fruit.viability, 0.0);
+  }
+
+  #[test]
+  fn fruit_germinates_only_in_spring_on_soil() {
+    let (species, oak) = oak();
+    let fruit = Fruit::new(species, &oak);
+    let probability = |season, ground, neighbours| {
+      germination_probability(fruit.viability, &oak, season, ground, neighbours, 1.0, 1.0)
+    };
+    assert!(probability(Season::Spring, Some(GroundType::Soil), 0) > 0.0);
+    assert!(probability(Season::Spring, Some(GroundType::Sand), 0) < probability(Season::Spring, Some(GroundType::Soil), 0));
+    assert_eq!(probability(Season::Autumn, Some(GroundType::Soil), 0), 0.0);
+    assert_eq!(probability(Season::Spring, Some(GroundType::Asphalt), 0), 0.0);
+    assert_eq!(probability(Season::Spring, None, 0), 0.0);
+    assert_eq!(probability(Season::Spring, Some(GroundType::Soil), oak.germination_max_density), 0.0);
+  }
+
+  #[test]
+  fn fruit_lasts_until_spring() {
+    for settings in [Settings::default(), Settings::load()] {
+      for (_, tree_type) in SpeciesRegistry::load().iter() {
+        // fallen at the start of autumn, the fruit waits through autumn and winter
+        assert!(tree_type.fruit_lifetime_days > 2.0 * settings.days_in_season as f32, "{}", tree_type.name);
+      }
+    }
+  }
+
+  fn resources(config: TreeConfig, elapsed_days: f32) -> Resources {
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = elapsed_days * Settings::default().day_duration;
+    let map = Map::test_square(10.0, 10.0);
+    let mut resources = Resources::default();
+    resources.insert(timers);
+    resources.insert(Settings::default());
+    resources.insert(SpeciesRegistry::from_config(config));
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(Weather::prepare("moderate"));
+    resources.insert(map);
+    resources.insert(SpatialIndex::<TreeProperties>::new(1.0));
+    resources.insert(SimRng::seeded(1));
+    resources.insert(Events::default());
+    resources
+  }
+
+  fn run(world: &mut World, resources: &mut Resources) {
+    let mut schedule = Schedule::builder()
+      .add_system(fruit_lifecycle_system())
+      .build();
+    schedule.execute(world, resources);
+  }
+
+  #[test]
+  fn fruit_is_eaten() {
+    let (species, oak) = oak();
+    let mut config = TreeConfig::load();
+    config.spieces[species.0 as usize].fruit_predation = 1.0;
+    config.spieces[species.0 as usize].fruit_caching = 0.0;
+    let mut world = World::default();
+    let mut resources = resources(config, 1.0);
+    let fruit = world.push((Fruit::new(species, &oak), Position(Vector3::zeros())));
+    run(&mut world, &mut resources);
+
+    assert!(world.entry(fruit).is_none());
+    let events = resources.get::<Events>().unwrap();
+    assert_eq!(events.iter().next(), Some(&Event::EntityDied { entity: fruit, cause: DeathCause::Eaten }));
+  }
+
+  #[test]
+  fn cached_fruit_is_carried_away() {
+    let (species, oak) = oak();
+    let mut config = TreeConfig::load();
+    config.spieces[species.0 as usize].fruit_predation = 1.0;
+    config.spieces[species.0 as usize].fruit_caching = 1.0;
+    config.spieces[species.0 as usize].germination_rate = 0.0;
+    let mut world = World::default();
+    let mut resources = resources(config, 1.0);
+    let fruit = world.push((Fruit::new(species, &oak), Position(Vector3::zeros())));
+    run(&mut world, &mut resources);
+
+    let entry = world.entry(fruit).unwrap();
+    assert_ne!(entry.get_component::<Position>().unwrap(), &Position(Vector3::zeros()));
+    assert!(resources.get::<Events>().unwrap().iter().next().is_none());
+  }
+
+  #[test]
+  fn rotten_fruit_is_removed() {
+    let (species, oak) = oak();
+    let mut world = World::default();
+    let mut resources = resources(TreeConfig::load(), oak.fruit_lifetime_days);
+    let fruit = world.push((Fruit::new(species, &oak), Position(Vector3::zeros())));
+    run(&mut world, &mut resources);
+
+    assert!(world.entry(fruit).is_none());
+    let events = resources.get::<Events>().unwrap();
+    assert_eq!(events.iter().next(), Some(&Event::EntityDied { entity: fruit, cause: DeathCause::Rotten }));
+  }
+
+  #[test]
+  fn pioneer_fruit_waits_for_gap() {
+    let registry = SpeciesRegistry::load();
+    let species = registry.find("birch").unwrap();
+    let mut config = TreeConfig::load();
+    config.spieces[species.0 as usize].germination_rate = 1.0;
+    config.spieces[species.0 as usize].fruit_predation = 0.0;
+    let birch = config.spieces[species.0 as usize].clone();
+    let mut world = World::default();
+    let mut resources = resources(config, 1.0);
+    resources.get_mut::<GrassLayer>().unwrap().shade(&Position(Vector3::zeros()), 1.0, 0.9);
+    let shaded = world.push((Fruit::new(species, &birch), Position(Vector3::zeros())));
+    let open = world.push((Fruit::new(species, &birch), Position(Vector3::new(4.0, 4.0, 0.0))));
+    run(&mut world, &mut resources);
+
+    assert!(world.entry(shaded).is_some());
+    assert!(world.entry(open).is_none());
+    let events = resources.get::<Events>().unwrap();
+    assert!(matches!(events.iter().next(), Some(Event::FruitGerminated { fruit, .. }) if *fruit == open));
+  }
+}
@@ -0,0 +1,330 @@
+use legion::*;
+use legion::systems::CommandBuffer;
+use rand::Rng;
+use super::timer::Timers;
+use super::climate::Weather;
+use super::settings::Settings;
+use super::map::Map;
+use super::movements::{ Position, Vector3 };
+use super::events::{ Events, Event };
+use super::fruit::{ Fruit, rot, germination_probability, predation_probability };
+use super::grass::GrassLayer;
+use super::random::{ SimRng, random_count };
+use super::dispersal::disperse;
+use super::raster::Raster;
+use super::spatial::SpatialIndex;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeProperties, place_tree };
+use super::genetics::Parents;
+use super::succession::can_germinate;
+use serde::{ Serialize, Deserialize };
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FruitRepresentation {
+  // every fallen fruit is an entity
+  Entities,
+  // fallen fruits are counted per map cell in FruitLayer
+  Layer,
+}
+
+// Fruits of one species fallen into a cell at the same time
+#[derive(Clone, Debug, PartialEq)]
+pub struct FruitPile {
+  pub species: SpeciesId,
+  pub amount: u32,
+  pub age: f32,
+  pub parents: Parents,
+}
+
+pub struct FruitLayer(Raster<Vec<FruitPile>>);
+
+impl FruitLayer {
+  pub fn covering(map: &Map, cell_size: f32) -> Self {
+    FruitLayer(Raster::covering(map, cell_size, Vec::new()))
+  }
+
+  // Returns false when position is outside of the layer
+  pub fn drop(&mut self, position: &Position, species: SpeciesId, amount: u32, parents: &Parents) -> bool {
+    if let Some(piles) = self.0.get_mut(position) {
+      let fresh = piles.iter_mut().find(|p| p.species == species && p.age == 0.0 && p.parents == *parents);
+      if let Some(fresh) = fresh {
+        fresh.amount += amount;
+      } else {
+        piles.push(FruitPile { species, amount, age: 0.0, parents: parents.clone() });
+      }
+      true
+    } else {
+      false
+    }
+  }
+
+  #[cfg(test)]
+  pub fn amount_at(&self, position: &Position, species: SpeciesId) -> u32 {
+    self.0.get(position)
+      .map(|piles| piles.iter().filter(|p| p.species == species).map(|p| p.amount).sum())
+      .unwrap_or(0)
+  }
+
+  #[cfg(test)]
+  pub fn total(&self, species: SpeciesId) -> u32 {
+    self.0.iter().flatten().filter(|p| p.species == species).map(|p| p.amount).sum()
+  }
+
+  // Takes the freshest fruit from the cell at position and turns it into an
+  // entity at `to`, e.g. where an animal carries it
+  pub fn pick_up(
+    &mut self,
+    position: &Position,
+    to: &Position,
+    species: Option<SpeciesId>,
+    registry: &SpeciesRegistry,
+    settings: &Settings,
+    commands: &mut CommandBuffer
+  ) -> Option<Entity> {
+    let piles = self.0.get_mut(position)?;
+    let ix = piles.iter()
+      .enumerate()
+      .filter(|(_, p)| species.is_none_or(|s| s == p.species))
+      .min_by(|(_, a), (_, b)| a.age.partial_cmp(&b.age).unwrap_or(std::cmp::Ordering::Equal))
+      .map(|(ix, _)| ix)?;
+    let pile = &mut piles[ix];
+    let tree_type = registry.get(pile.species);
+    let mut fruit = Fruit { parents: pile.parents.clone(), ..Fruit::new(pile.species, tree_type) };
+    rot(&mut fruit, tree_type, pile.age, settings.day_duration);
+    pile.amount -= 1;
+    if pile.amount == 0 {
+      piles.remove(ix);
+    }
+    Some(commands.push((fruit, *to)))
+  }
+}
+
+#[system]
+#[allow(clippy::too_many_arguments)]
+pub fn update_fruit_layer(
+  commands: &mut CommandBuffer,
+  #[resource] layer: &mut FruitLayer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] weather: &Weather,
+  #[resource] map: &Map,
+  #[resource] grass: &GrassLayer,
+  #[resource] trees: &SpatialIndex<TreeProperties>,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let cell_size = layer.0.cell_size();
+  // Fruits animals have taken to cache, picked up when the cells are done
+  let mut cached: Vec<(Position, SpeciesId, u32)> = Vec::new();
+  for ix in 0..layer.0.len() {
+    let center = layer.0.cell_center(ix);
+    let ground_light = grass.light_at(&center);
+    let piles = layer.0.cell_mut(ix);
+    for pile in piles.iter_mut() {
+      let tree_type = registry.get(pile.species);
+      pile.age += time.long.elapsed_seconds;
+      let viability = 1.0 - pile.age / (tree_type.fruit_lifetime_days * settings.day_duration);
+      if viability <= 0.0 {
+        pile.amount = 0;
+        continue;
+      }
+      // Rounded at random, so small piles get eaten too
+      let eaten = pile.amount as f64 * predation_probability(tree_type, time.long.elapsed_seconds, settings.day_duration);
+      let eaten = eaten.floor() as u32 + rng.gen_bool(eaten.fract()) as u32;
+      let carried = random_count(eaten as f32 * tree_type.fruit_caching, rng).min(eaten);
+      pile.amount -= eaten - carried;
+      if carried > 0 {
+        cached.push((center, pile.species, carried));
+      }
+
+      if !can_germinate(tree_type, ground_light) {
+        continue;
+      }
+      let neighbours = trees.within_radius(&center, tree_type.germination_radius).count();
+      let probability = germination_probability(
+        viability,
+        tree_type,
+        time.current_season,
+        map.ground_type(&center),
+        neighbours,
+        time.long.elapsed_seconds,
+        settings.day_duration
+      );
+      // Only one seedling per cell and tick, to respect germination density
+      if pile.amount > 0 && rng.gen_bool(1.0 - (1.0 - probability).powi(pile.amount as i32)) {
+        pile.amount -= 1;
+        let shift = Vector3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), 0.0) * cell_size;
+        let position = center + Position(shift);
+        let genome = pile.parents.child(&tree_type.genetics, rng);
+        let entity = place_tree(commands, position, pile.species, genome);
+        events.emit(Event::TreeSeeded { parent: None, entity, position });
+      }
+    }
+    piles.retain(|pile| pile.amount > 0);
+  }
+
+  for (position, species, amount) in cached {
+    let tree_type = registry.get(species);
+    for _ in 0..amount {
+      let cache = disperse(&tree_type.dispersal, &position, 0.0, &weather.wind, map, rng).unwrap_or(position);
+      if layer.pick_up(&position, &cache, Some(species), registry, settings, commands).is_none() {
+        break;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::grass::GrassConfig;
+  use num::traits::Zero;
+  use std::time::Instant;
+  use super::super::fruit::fruit_lifecycle_system;
+  use super::super::timer::Season;
+  use super::super::tree::TreeConfig;
+
+  fn resources_for(map: Map, elapsed_seconds: f32) -> Resources {
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = elapsed_seconds;
+    let mut resources = Resources::default();
+    resources.insert(FruitLayer::covering(&map, 1.0));
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(Weather::prepare("moderate"));
+    resources.insert(map);
+    resources.insert(timers);
+    resources.insert(Settings::default());
+    resources.insert(SpeciesRegistry::load());
+    resources.insert(SpatialIndex::<TreeProperties>::new(1.0));
+    resources.insert(SimRng::seeded(1));
+    resources.insert(Events::default());
+    resources
+  }
+
+  #[test]
+  fn fruits_are_counted_per_cell() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let mut layer = FruitLayer::covering(&Map::test_square(10.0, 10.0), 1.0);
+    assert!(layer.drop(&Position::zero(), oak, 10, &Parents::default()));
+    assert!(layer.drop(&Position(Vector3::new(0.5, 0.5, 0.0)), oak, 5, &Parents::default()));
+    assert!(layer.drop(&Position(Vector3::new(3.0, 3.0, 0.0)), oak, 1, &Parents::default()));
+    assert!(!layer.drop(&Position(Vector3::new(30.0, 3.0, 0.0)), oak, 1, &Parents::default()));
+
+    assert_eq!(layer.amount_at(&Position::zero(), oak), 15);
+    assert_eq!(layer.total(oak), 16);
+  }
+
+  #[test]
+  fn picked_fruit_becomes_entity() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let settings = Settings::default();
+    let mut layer = FruitLayer::covering(&Map::test_square(10.0, 10.0), 1.0);
+    let mut world = World::default();
+    let mut commands = CommandBuffer::new(&world);
+    layer.drop(&Position::zero(), oak, 1, &Parents::default());
+
+    let picked = layer.pick_up(&Position::zero(), &Position::zero(), Some(oak), &registry, &settings, &mut commands);
+    assert!(picked.is_some());
+    assert!(layer.pick_up(&Position::zero(), &Position::zero(), Some(oak), &registry, &settings, &mut commands).is_none());
+    commands.flush(&mut world, &mut Resources::default());
+
+    let entry = world.entry(picked.unwrap()).unwrap();
+    assert_eq!(entry.get_component::<Fruit>().unwrap().species, oak);
+    assert_eq!(layer.total(oak), 0);
+  }
+
+  #[test]
+  fn rotten_piles_are_removed() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let lifetime = registry.get(oak).fruit_lifetime_days * Settings::default().day_duration;
+    let mut resources = resources_for(Map::test_square(10.0, 10.0), lifetime);
+    resources.get_mut::<FruitLayer>().unwrap().drop(&Position::zero(), oak, 100, &Parents::default());
+    let mut world = World::default();
+    let mut schedule = Schedule::builder()
+      .add_system(update_fruit_layer_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+    assert_eq!(resources.get::<FruitLayer>().unwrap().total(oak), 0);
+  }
+
+  #[test]
+  fn cached_fruits_become_entities() {
+    let mut config = TreeConfig::load();
+    let oak = SpeciesRegistry::from_config(TreeConfig::load()).find("oak").unwrap();
+    config.spieces[oak.0 as usize].fruit_predation = 1.0;
+    config.spieces[oak.0 as usize].fruit_caching = 1.0;
+    let mut resources = resources_for(Map::test_square(100.0, 100.0), Settings::default().day_duration);
+    resources.insert(SpeciesRegistry::from_config(config));
+    resources.get_mut::<Timers>().unwrap().current_season = Season::Winter;
+    resources.get_mut::<FruitLayer>().unwrap().drop(&Position::zero(), oak, 10, &Parents::default());
+    let mut world = World::default();
+    let mut schedule = Schedule::builder()
+      .add_system(update_fruit_layer_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    assert_eq!(resources.get::<FruitLayer>().unwrap().total(oak), 0);
+    let fruits: Vec<(&Fruit, &Position)> = <(&Fruit, &Position)>::query().iter(&world).collect();
+    assert_eq!(fruits.len(), 10);
+    assert!(fruits.iter().all(|(fruit, _)| fruit.species == oak));
+    assert!(fruits.iter().any(|(_, position)| **position != Position::zero()));
+  }
+
+  // cargo test --release -- --ignored bench_fruit_representations --nocapture
+  #[test]
+  #[ignore]
+  fn bench_fruit_representations() {
+    let trees = 1000;
+    let fruits_per_tree = 500;
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let map = Map::test_square(1000.0, 1000.0);
+    let mut rng = SimRng::seeded(1);
+    let positions: Vec<Position> = (0..trees)
+      .map(|_| Position(Vector3::new(rng.gen_range(-490.0..490.0), rng.gen_range(-490.0..490.0), 0.0)))
+      .collect();
+    let fruit_position = |tree: &Position, rng: &mut SimRng| {
+      *tree + Position(Vector3::new(rng.gen_range(-5.0..5.0), rng.gen_range(-5.0..5.0), 0.0))
+    };
+
+    let mut world = World::default();
+    let mut resources = resources_for(map, 1.0);
+    let started = Instant::now();
+    let mut commands = CommandBuffer::new(&world);
+    for tree in &positions {
+      for _ in 0..fruits_per_tree {
+        commands.push((Fruit::new(oak, registry.get(oak)), fruit_position(tree, &mut rng)));
+      }
+    }
+    commands.flush(&mut world, &mut resources);
+    println!("dropping {} fruit entities: {:?}", trees * fruits_per_tree, started.elapsed());
+    let mut schedule = Schedule::builder().add_system(fruit_lifecycle_system()).build();
+    let started = Instant::now();
+    schedule.execute(&mut world, &mut resources);
+    let entities_tick = started.elapsed();
+    println!("fruit entities tick: {:?}, entities: {}", entities_tick, world.len());
+
+    let mut world = World::default();
+    let started = Instant::now();
+    {
+      let mut layer = resources.get_mut::<FruitLayer>().unwrap();
+      for tree in &positions {
+        for _ in 0..fruits_per_tree {
+          layer.drop(&fruit_position(tree, &mut rng), oak, 1, &Parents::default());
+        }
+      }
+    }
+    println!("dropping {} fruits into layer: {:?}", trees * fruits_per_tree, started.elapsed());
+    let mut schedule = Schedule::builder().add_system(update_fruit_layer_system()).build();
+    let started = Instant::now();
+    schedule.execute(&mut world, &mut resources);
+    let layer_tick = started.elapsed();
+    println!("fruit layer tick: {:?}, entities: {}", layer_tick, world.len());
+    assert!(layer_tick < entities_tick, "layer tick {:?}, entities tick {:?}", layer_tick, entities_tick);
+  }
+}
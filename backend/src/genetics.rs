@@ -0,0 +1,386 @@
+use legion::*;
+use legion::world::SubWorld;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use serde::{ Serialize, Deserialize };
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fs::{ self, OpenOptions };
+use std::io::Write;
+use std::path::Path;
+use super::timer::Season;
+use super::settings::Settings;
+use super::events::{ Events, Event };
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::TreeProperties;
+
+// Heritable traits of a single tree. Every trait is relative to the TreeType
+// of the species, so the default genome gives the species' average tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Genome {
+  pub growth_speed: f32, // multiplier of growth
+  pub frost_tolerance: f32, // degrees below species' frost_tempreture the tree survives
+  pub phenology_offset: f32, // delay of calendar phenology, part of the year
+  pub thermal_offset: f32, // delay of degree-day phenology, part of every threshold
+  pub fertility: f32, // multiplier of fruit and seed amount
+  pub generation: u32,
+}
+
+impl Default for Genome {
+  fn default() -> Self {
+    Genome {
+      growth_speed: 1.0,
+      frost_tolerance: 0.0,
+      phenology_offset: 0.0,
+      thermal_offset: 0.0,
+      fertility: 1.0,
+      generation: 0,
+    }
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GeneticsConfig {
+  pub mutation_rate: f32, // probability for every trait to mutate in offspring
+  pub growth_speed_variation: f32, // standard deviation of mutation
+  pub frost_tolerance_variation: f32,
+  pub phenology_variation: f32,
+  pub thermal_variation: f32,
+  pub fertility_variation: f32,
+  pub pollination_radius: f32, // flowering conspecifics this close pollinate and fertilize the tree
+}
+
+// Genomes of both parents carried by seeds and fruits until germination
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Parents {
+  pub mother: Genome,
+  pub father: Option<Genome>,
+}
+
+impl Parents {
+  pub fn child<R: Rng>(&self, config: &GeneticsConfig, rng: &mut R) -> Genome {
+    Genome::inherit(&self.mother, self.father.as_ref(), config, rng)
+  }
+}
+
+// Parents of a single fruit or seed, the father is one of the pollen donors
+pub fn pick_parents<R: Rng>(mother: &Genome, pollen: &[Genome], rng: &mut R) -> Parents {
+  Parents { mother: mother.clone(), father: pollen.choose(rng).cloned() }
+}
+
+// Box-Muller transform
+fn gaussian<R: Rng>(sigma: f32, rng: &mut R) -> f32 {
+  let u1: f32 = 1.0 - rng.gen::<f32>();
+  let u2: f32 = rng.gen();
+  sigma * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+fn inherit_trait<R: Rng>(mother: f32, father: Option<f32>, sigma: f32, rate: f32, rng: &mut R) -> f32 {
+  let value = match father {
+    Some(father) => if rng.gen::<bool>() { mother } else { father },
+    None => mother
+  };
+  if rng.gen::<f32>() < rate {
+    value + gaussian(sigma, rng)
+  } else {
+    value
+  }
+}
+
+impl Genome {
+  // Every trait is taken from a random parent and then possibly mutated.
+  // Without a father the tree is self-pollinated.
+  pub fn inherit<R: Rng>(mother: &Genome, father: Option<&Genome>, config: &GeneticsConfig, rng: &mut R) -> Genome {
+    let rate = config.mutation_rate;
+    Genome {
+      growth_speed: inherit_trait(
+        mother.growth_speed, father.map(|g| g.growth_speed), config.growth_speed_variation, rate, rng
+      ).max(0.0),
+      frost_tolerance: inherit_trait(
+        mother.frost_tolerance, father.map(|g| g.frost_tolerance), config.frost_tolerance_variation, rate, rng
+      ),
+      phenology_offset: inherit_trait(
+        mother.phenology_offset, father.map(|g| g.phenology_offset), config.phenology_variation, rate, rng
+      ),
+      thermal_offset: inherit_trait(
+        mother.thermal_offset, father.map(|g| g.thermal_offset), config.thermal_variation, rate, rng
+      ),
+      fertility: inherit_trait(
+        mother.fertility, father.map(|g| g.fertility), config.fertility_variation, rate, rng
+      ).max(0.0),
+      generation: mother.generation.max(father.map_or(0, |g| g.generation)) + 1,
+    }
+  }
+
+  // Time of year as seen by this tree's phenology
+  pub fn year_time(&self, time_of_year: f32) -> f32 {
+    (time_of_year - self.phenology_offset).rem_euclid(1.0)
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraitStats {
+  pub mean: f32,
+  pub std: f32,
+}
+
+impl TraitStats {
+  fn of(values: &[f32]) -> Self {
+    if values.is_empty() {
+      return TraitStats::default();
+    }
+    let n = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n;
+    TraitStats { mean, std: variance.sqrt() }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenerationStats {
+  pub count: usize,
+  pub growth_speed: TraitStats,
+  pub frost_tolerance: TraitStats,
+  pub phenology_offset: TraitStats,
+  pub thermal_offset: TraitStats,
+  pub fertility: TraitStats,
+}
+
+impl GenerationStats {
+  pub fn of<'a>(genomes: impl Iterator<Item = &'a Genome>) -> Self {
+    let genomes: Vec<&Genome> = genomes.collect();
+    let stats = |f: fn(&Genome) -> f32| TraitStats::of(&genomes.iter().map(|g| f(g)).collect::<Vec<_>>());
+    GenerationStats {
+      count: genomes.len(),
+      growth_speed: stats(|g| g.growth_speed),
+      frost_tolerance: stats(|g| g.frost_tolerance),
+      phenology_offset: stats(|g| g.phenology_offset),
+      thermal_offset: stats(|g| g.thermal_offset),
+      fertility: stats(|g| g.fertility),
+    }
+  }
+}
+
+// Trait distributions of living trees per species and generation
+#[derive(Default)]
+pub struct GenomeMetrics {
+  generations: HashMap<(SpeciesId, u32), GenerationStats>,
+  year: u32, // years closed since the start
+}
+
+impl GenomeMetrics {
+  pub fn get(&self, species: SpeciesId, generation: u32) -> Option<&GenerationStats> {
+    self.generations.get(&(species, generation))
+  }
+
+  pub fn generations(&self, species: SpeciesId) -> Vec<u32> {
+    let mut generations: Vec<u32> = self.generations.keys()
+      .filter(|(s, _)| *s == species)
+      .map(|(_, generation)| *generation)
+      .collect();
+    generations.sort_unstable();
+    generations
+  }
+
+  // Rows of year,species,generation,count and mean and std of every trait
+  pub fn csv_rows(&self, registry: &SpeciesRegistry) -> Vec<String> {
+    let mut keys: Vec<&(SpeciesId, u32)> = self.generations.keys().collect();
+    keys.sort_by_key(|(species, generation)| (species.0, *generation));
+    keys.into_iter().map(|key| {
+      let stats = &self.generations[key];
+      let traits = [&stats.growth_speed, &stats.frost_tolerance, &stats.phenology_offset, &stats.thermal_offset, &stats.fertility];
+      let traits: Vec<String> = traits.iter().map(|t| format!("{},{}", t.mean, t.std)).collect();
+      format!("{},{},{},{},{}", self.year, registry.get(key.0).name, key.1, stats.count, traits.join(","))
+    }).collect()
+  }
+}
+
+const GENOME_CSV_HEADER: &str = "year,species,generation,count,\
+  growth_speed,growth_speed_std,frost_tolerance,frost_tolerance_std,\
+  phenology_offset,phenology_offset_std,thermal_offset,thermal_offset_std,fertility,fertility_std";
+
+fn append_rows(directory: &str, rows: &[String]) {
+  let path = Path::new(directory).join("genomes.csv");
+  let written = fs::create_dir_all(directory).and_then(|_| {
+    let new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if new {
+      writeln!(file, "{}", GENOME_CSV_HEADER)?;
+    }
+    rows.iter().try_for_each(|row| writeln!(file, "{}", row))
+  });
+  if let Err(error) = written {
+    println!("cannot write genome metrics to {:?}: {}", path, error);
+  }
+}
+
+#[system]
+#[read_component(SpeciesId)]
+#[read_component(Genome)]
+#[read_component(TreeProperties)]
+pub fn update_genome_metrics(world: &mut SubWorld, #[resource] metrics: &mut GenomeMetrics) {
+  let mut groups: HashMap<(SpeciesId, u32), Vec<&Genome>> = HashMap::new();
+  let mut query = <(&SpeciesId, &Genome)>::query().filter(component::<TreeProperties>());
+  for (species, genome) in query.iter(world) {
+    groups.entry((*species, genome.generation)).or_default().push(genome);
+  }
+  metrics.generations = groups.into_iter()
+    .map(|(key, genomes)| (key, GenerationStats::of(genomes.into_iter())))
+    .collect();
+}
+
+// Appends the metrics to genomes.csv in the output directory when a year
+// ends with the winter
+#[system]
+pub fn export_genome_metrics(
+  #[resource] metrics: &mut GenomeMetrics,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] settings: &Settings,
+  #[resource] events: &Events,
+) {
+  let year_closed = events.iter().any(|event| matches!(event, Event::SeasonChanged { to: Season::Spring, .. }));
+  if year_closed {
+    if let Some(directory) = &settings.output_directory {
+      append_rows(directory, &metrics.csv_rows(registry));
+    }
+    metrics.year += 1;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::random::SimRng;
+
+  fn config(mutation_rate: f32) -> GeneticsConfig {
+    GeneticsConfig {
+      mutation_rate,
+      growth_speed_variation: 0.1,
+      frost_tolerance_variation: 1.0,
+      phenology_variation: 0.01,
+      thermal_variation: 0.1,
+      fertility_variation: 0.1,
+      pollination_radius: 10.0,
+    }
+  }
+
+  #[test]
+  fn offspring_is_copy_of_mother_without_mutation() {
+    let mut rng = SimRng::seeded(1);
+    let mother = Genome { growth_speed: 1.3, frost_tolerance: 2.0, ..Genome::default() };
+    let child = Genome::inherit(&mother, None, &config(0.0), &mut rng);
+    assert_eq!(child, Genome { generation: 1, ..mother });
+  }
+
+  #[test]
+  fn traits_are_taken_from_both_parents() {
+    let mut rng = SimRng::seeded(1);
+    let mother = Genome { growth_speed: 1.0, ..Genome::default() };
+    let father = Genome { growth_speed: 2.0, generation: 3, ..Genome::default() };
+    let children: Vec<Genome> = (0..100)
+      .map(|_| Genome::inherit(&mother, Some(&father), &config(0.0), &mut rng))
+      .collect();
+    assert!(children.iter().any(|c| c.growth_speed == 1.0));
+    assert!(children.iter().any(|c| c.growth_speed == 2.0));
+    assert!(children.iter().all(|c| c.generation == 4));
+  }
+
+  #[test]
+  fn mutations_spread_traits() {
+    let mut rng = SimRng::seeded(1);
+    let mother = Genome::default();
+    let children: Vec<Genome> = (0..1000)
+      .map(|_| Genome::inherit(&mother, None, &config(1.0), &mut rng))
+      .collect();
+    let stats = GenerationStats::of(children.iter());
+    assert_eq!(stats.count, 1000);
+    assert!((stats.growth_speed.mean - 1.0).abs() < 0.02);
+    assert!((stats.growth_speed.std - 0.1).abs() < 0.02);
+    assert!((stats.frost_tolerance.std - 1.0).abs() < 0.1);
+  }
+
+  #[test]
+  fn phenology_offset_shifts_year_time() {
+    let genome = Genome { phenology_offset: 0.1, ..Genome::default() };
+    assert!((genome.year_time(0.5) - 0.4).abs() < 1e-6);
+    assert!((genome.year_time(0.05) - 0.95).abs() < 1e-6);
+  }
+
+  #[test]
+  fn every_fruit_picks_its_father() {
+    let mut rng = SimRng::seeded(1);
+    let mother = Genome::default();
+    let pollen: Vec<Genome> = (0..3).map(|i| Genome { fertility: i as f32, ..Genome::default() }).collect();
+    let fathers: Vec<f32> = (0..100)
+      .map(|_| pick_parents(&mother, &pollen, &mut rng).father.unwrap().fertility)
+      .collect();
+    assert!((0..3).all(|i| fathers.contains(&(i as f32))));
+    assert_eq!(pick_parents(&mother, &[], &mut rng).father, None);
+  }
+
+  #[test]
+  fn metrics_are_grouped_by_generation() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let mut world = World::default();
+    for growth_speed in &[1.0, 2.0] {
+      let genome = Genome { growth_speed: *growth_speed, generation: 1, ..Genome::default() };
+      world.push((oak, genome, TreeProperties::new(0.0, 1.0)));
+    }
+    world.push((oak, Genome::default(), TreeProperties::new(0.0, 1.0)));
+    // seeds and deadwood are not counted
+    world.push((oak, Genome::default()));
+
+    let mut resources = Resources::default();
+    resources.insert(GenomeMetrics::default());
+    let mut schedule = Schedule::builder()
+      .add_system(update_genome_metrics_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let metrics = resources.get::<GenomeMetrics>().unwrap();
+    assert_eq!(metrics.generations(oak), vec!(0, 1));
+    assert_eq!(metrics.get(oak, 0).unwrap().count, 1);
+    let first = metrics.get(oak, 1).unwrap();
+    assert_eq!(first.count, 2);
+    assert_eq!(first.growth_speed, TraitStats { mean: 1.5, std: 0.5 });
+    let rows = metrics.csv_rows(&SpeciesRegistry::load());
+    assert_eq!(rows.len(), 2);
+    assert!(rows[1].starts_with("0,oak,1,2,1.5,0.5,"));
+  }
+
+  #[test]
+  fn metrics_are_exported_when_year_ends() {
+    let directory = std::env::temp_dir().join("genome_metrics_test");
+    let _ = fs::remove_dir_all(&directory);
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let mut world = World::default();
+    world.push((oak, Genome::default(), TreeProperties::new(0.0, 1.0)));
+    let mut resources = Resources::default();
+    resources.insert(GenomeMetrics::default());
+    resources.insert(registry);
+    resources.insert(Settings { output_directory: Some(directory.to_string_lossy().into()), ..Settings::default() });
+    resources.insert(Events::default());
+    let mut schedule = Schedule::builder()
+      .add_system(update_genome_metrics_system())
+      .add_system(export_genome_metrics_system())
+      .build();
+    for to in [Season::Summer, Season::Spring, Season::Summer, Season::Spring] {
+      {
+        let mut events = resources.get_mut::<Events>().unwrap();
+        events.clear();
+        events.emit(Event::SeasonChanged { from: Season::Winter, to });
+      }
+      schedule.execute(&mut world, &mut resources);
+    }
+
+    let csv = fs::read_to_string(directory.join("genomes.csv")).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("year,species,generation,count,growth_speed,"));
+    assert!(lines[1].starts_with("0,oak,0,1,"));
+    assert!(lines[2].starts_with("1,oak,0,1,"));
+    let _ = fs::remove_dir_all(&directory);
+  }
+}
@@ -0,0 +1,265 @@
+use legion::*;
+use legion::world::SubWorld;
+use serde::{ Serialize, Deserialize };
+use super::timer::Timers;
+use super::settings::Settings;
+use super::climate::Weather;
+use super::map::Map;
+use super::movements::{ Position, Vector3 };
+use super::raster::Raster;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::growth::{ GrowEffectiviness, map_to_effectiveness };
+use super::allometry::crown_radius;
+use super::phenology::year_days;
+use super::spatial::distance_2d;
+use super::tree::{ TreeProperties, TreeState, min, max };
+
+static GRASS_CONFIG: &str = include_str!("../config/grass.yaml");
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GrassConfig {
+  pub max_biomass: f32,
+  pub min_biomass: f32,
+  pub growth_rate: f32,
+  pub effectiveness: GrowEffectiviness,
+  pub dieback_tempreture: f32, // grass above min_biomass dies back when it is colder
+  pub dieback_rate: f32,
+}
+
+impl GrassConfig {
+  pub fn load() -> Self {
+    serde_yaml::from_str(GRASS_CONFIG).unwrap()
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrassCell {
+  pub biomass: f32, // kilograms per square meter
+  pub moisture: f32,
+  pub light: f32, // light left under tree canopies
+}
+
+// Grass and herbs covering the ground, simulated per map cell
+pub struct GrassLayer {
+  config: GrassConfig,
+  cells: Raster<GrassCell>,
+}
+
+impl GrassLayer {
+  pub fn covering(map: &Map, cell_size: f32, config: GrassConfig) -> Self {
+    let empty = GrassCell { biomass: 0.0, moisture: 0.0, light: 1.0 };
+    let mut cells = Raster::covering(map, cell_size, empty);
+    for ix in 0..cells.len() {
+      let moisture = map.ground_type(&cells.cell_center(ix)).map_or(0.0, |ground| ground.moisture());
+      let cell = cells.cell_mut(ix);
+      cell.moisture = moisture;
+      if moisture > 0.0 {
+        cell.biomass = config.min_biomass;
+      }
+    }
+    GrassLayer { config, cells }
+  }
+
+  pub fn biomass_at(&self, position: &Position) -> f32 {
+    self.cells.get(position).map_or(0.0, |cell| cell.biomass)
+  }
+
+  pub fn light_at(&self, position: &Position) -> f32 {
+    self.cells.get(position).map_or(1.0, |cell| cell.light)
+  }
+
+  // Total biomass in kilograms
+  pub fn total(&self) -> f32 {
+    let area = self.cells.cell_size() * self.cells.cell_size();
+    self.cells.iter().map(|cell| cell.biomass).sum::<f32>() * area
+  }
+
+  // Burns grass of the cell down to the ground, it regrows from roots
+  pub fn burn(&mut self, position: &Position) {
+    if let Some(cell) = self.cells.get_mut(position) {
+      cell.biomass = 0.0;
+    }
+  }
+
+  // Multiplies light of cells under the crown by the part it lets through
+  pub fn shade(&mut self, position: &Position, radius: f32, opacity: f32) {
+    let size = self.cells.cell_size();
+    let steps = (radius / size).ceil() as i32;
+    for dx in -steps..=steps {
+      for dy in -steps..=steps {
+        let point = Position(position.0 + Vector3::new(dx as f32 * size, dy as f32 * size, 0.0));
+        if let Some(ix) = self.cells.cell_index(&point) {
+          if distance_2d(&self.cells.cell_center(ix).0, &position.0) <= radius {
+            self.cells.cell_mut(ix).light *= 1.0 - opacity;
+          }
+        }
+      }
+    }
+  }
+
+  fn reset_light(&mut self) {
+    for cell in self.cells.iter_mut() {
+      cell.light = 1.0;
+    }
+  }
+
+  pub fn grow(&mut self, tempreture: f32, days: f32) {
+    let config = &self.config;
+    let effectiveness = map_to_effectiveness(tempreture, &config.effectiveness);
+    for cell in self.cells.iter_mut().filter(|cell| cell.moisture > 0.0) {
+      grow_cell(cell, config, effectiveness, tempreture, days);
+    }
+  }
+}
+
+// Logistic growth limited by moisture and light, dieback in cold weather
+pub fn grow_cell(cell: &mut GrassCell, config: &GrassConfig, effectiveness: f32, tempreture: f32, days: f32) {
+  let biomass = max(cell.biomass, config.min_biomass);
+  let rate = config.growth_rate * effectiveness * cell.moisture * cell.light;
+  let growth = rate * biomass * (1.0 - biomass / config.max_biomass) * days;
+  let mut biomass = min(biomass + max(0.0, growth), config.max_biomass);
+  if tempreture < config.dieback_tempreture {
+    let alive = (1.0 - config.dieback_rate).powf(days);
+    biomass = config.min_biomass + (biomass - config.min_biomass) * alive;
+  }
+  cell.biomass = biomass;
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(SpeciesId)]
+#[read_component(TreeProperties)]
+pub fn update_grass(
+  world: &mut SubWorld,
+  #[resource] grass: &mut GrassLayer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] weather: &Weather,
+  #[resource] registry: &SpeciesRegistry,
+) {
+  grass.reset_light();
+  let mut query = <(&Position, &SpeciesId, &TreeProperties)>::query();
+  let leafy = query.iter(world)
+    .filter(|(_, _, properties)| !matches!(properties.state, TreeState::Sleep | TreeState::Falling));
+  for (position, species, properties) in leafy {
+    let tree_type = registry.get(*species);
+    grass.shade(position, crown_radius(properties, tree_type), tree_type.crown_opacity);
+  }
+  grass.grow(weather.current_tempreture, year_days(time.long.elapsed_seconds, settings));
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::time::{ Duration, Instant };
+  use rand::Rng;
+  use num::traits::Zero;
+  use super::super::random::SimRng;
+  use super::super::tree::place_tree_test;
+
+  fn grown(cell: GrassCell, tempreture: f32, days: f32) -> f32 {
+    let config = GrassConfig::load();
+    let effectiveness = map_to_effectiveness(tempreture, &config.effectiveness);
+    let mut cell = cell;
+    grow_cell(&mut cell, &config, effectiveness, tempreture, days);
+    cell.biomass
+  }
+
+  fn cell(light: f32) -> GrassCell {
+    GrassCell { biomass: 0.1, moisture: 0.7, light }
+  }
+
+  #[test]
+  fn grass_grows_in_warm_weather_up_to_maximum() {
+    let config = GrassConfig::load();
+    assert!(grown(cell(1.0), 20.0, 1.0) > 0.1);
+    assert!((grown(cell(1.0), 20.0, 10000.0) - config.max_biomass).abs() < 1e-3);
+  }
+
+  #[test]
+  fn shade_and_drought_slow_grass_down() {
+    assert!(grown(cell(0.2), 20.0, 10.0) < grown(cell(1.0), 20.0, 10.0));
+    let dry = GrassCell { moisture: 0.1, ..cell(1.0) };
+    assert!(grown(dry, 20.0, 10.0) < grown(cell(1.0), 20.0, 10.0));
+  }
+
+  #[test]
+  fn grass_dies_back_in_winter_but_keeps_roots() {
+    let config = GrassConfig::load();
+    assert!(grown(cell(1.0), -5.0, 10.0) < 0.1);
+    assert!((grown(cell(1.0), -5.0, 10000.0) - config.min_biomass).abs() < 1e-3);
+  }
+
+  // Light under an oak in the state
+  fn light_under_oak(state: TreeState) -> f32 {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    let oak = place_tree_test(&mut world, &registry, "oak", Position::zero(), 0.0, 10.0).unwrap();
+    world.entry(oak).unwrap().get_component_mut::<TreeProperties>().unwrap().state = state;
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = Settings::default().day_duration;
+    let mut weather = Weather::prepare("moderate");
+    weather.current_tempreture = 20.0;
+    let map = Map::test_square(40.0, 40.0);
+    let mut resources = Resources::default();
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(timers);
+    resources.insert(Settings::default());
+    resources.insert(weather);
+    resources.insert(registry);
+    let mut schedule = Schedule::builder()
+      .add_system(update_grass_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let grass = resources.get::<GrassLayer>().unwrap();
+    let open = Position(Vector3::new(15.0, 15.0, 0.0));
+    assert_eq!(grass.light_at(&open), 1.0);
+    if grass.light_at(&Position::zero()) < 1.0 {
+      assert!(grass.biomass_at(&Position::zero()) < grass.biomass_at(&open));
+    }
+    grass.light_at(&Position::zero())
+  }
+
+  #[test]
+  fn trees_shade_grass_under_their_crowns() {
+    assert!(light_under_oak(TreeState::Ripening) < 1.0);
+  }
+
+  #[test]
+  fn leafless_trees_do_not_shade() {
+    assert_eq!(light_under_oak(TreeState::Sleep), 1.0);
+    assert_eq!(light_under_oak(TreeState::Falling), 1.0);
+  }
+
+  // cargo test --release -- --ignored bench_grass_layer --nocapture
+  #[test]
+  #[ignore]
+  fn bench_grass_layer() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let map = Map::test_square(1000.0, 1000.0);
+    let mut rng = SimRng::seeded(1);
+    let mut grass = GrassLayer::covering(&map, 1.0, GrassConfig::load());
+    let trees: Vec<(Position, TreeProperties)> = (0..10000)
+      .map(|_| {
+        let position = Position(Vector3::new(rng.gen_range(-490.0..490.0), rng.gen_range(-490.0..490.0), 0.0));
+        (position, TreeProperties::new(0.0, rng.gen_range(1.0..15.0)))
+      })
+      .collect();
+
+    let started = Instant::now();
+    grass.reset_light();
+    for (position, properties) in &trees {
+      grass.shade(position, crown_radius(properties, registry.get(oak)), 0.8);
+    }
+    let shading = started.elapsed();
+    println!("shading of {} cells by {} trees: {:?}", grass.cells.len(), trees.len(), shading);
+    let started = Instant::now();
+    grass.grow(20.0, 1.0);
+    let growth = started.elapsed();
+    println!("growth: {:?}, total biomass {}", growth, grass.total());
+    // a slow tick has to fit into a second
+    assert!(shading + growth < Duration::from_secs(1), "shading {:?}, growth {:?}", shading, growth);
+  }
+}
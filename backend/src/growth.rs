@@ -0,0 +1,246 @@
+use legion::*;
+use serde::{ Serialize, Deserialize };
+use super::timer::Timers;
+use super::settings::Settings;
+use super::interpolations::lerp_map;
+use super::climate::Weather;
+use super::movements::Position;
+use super::map::Map;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, TreeState, clamp, min };
+use super::genetics::Genome;
+use super::allometry::growth_saturation;
+use super::drought::water_limitation;
+use super::succession::light_response;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrowthModel {
+  // size grows with growth_speed during Blossom and Ripening, slowing down near max_size
+  Simple,
+  // size grows from resources gathered according to temperature and soil
+  ResourceBased,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GrowEffectiviness {
+  starting_temp: f32,
+  ending_temp: f32,
+  distribution: Vec<f32>
+}
+
+pub fn map_to_effectiveness(temp: f32, effectiveness: &GrowEffectiviness) -> f32 {
+  let t = clamp(
+    (temp - effectiveness.starting_temp) / (effectiveness.ending_temp - effectiveness.starting_temp),
+    0.0, 1.0
+  );
+  lerp_map(t, &effectiveness.distribution)
+}
+
+// Only fruits cost resources to ripen, a tree which has not blossomed leafs
+// out for free
+fn transition_cost(from: &TreeState, to: &TreeState, tree_type: &TreeType) -> f32 {
+  match (from, to) {
+    (_, TreeState::Blossom) => tree_type.blossom_cost,
+    (TreeState::Blossom, TreeState::Ripening) => tree_type.ripening_cost,
+    _ => 0.0
+  }
+}
+
+// Returns the state tree is able to enter. When the tree cannot pay for the
+// new state it stays in the current one until enough resources are gathered.
+// A tree which could not afford to blossom skips fruiting this year, but
+// still grows through the rest of the season.
+pub fn pay_for_transition(properties: &mut TreeProperties, tree_type: &TreeType, new_state: TreeState) -> TreeState {
+  if new_state == properties.state {
+    return new_state;
+  }
+  let cost = transition_cost(&properties.state, &new_state, tree_type);
+  if cost <= properties.resources {
+    properties.resources -= cost;
+    new_state
+  } else {
+    properties.state.clone()
+  }
+}
+
+fn is_growing(properties: &TreeProperties) -> bool {
+  matches!(properties.state, TreeState::Blossom | TreeState::Ripening)
+}
+
+pub fn grow_simple(properties: &mut TreeProperties, tree_type: &TreeType, genome: &Genome, elapsed_seconds: f32) {
+  if is_growing(properties) && properties.size < tree_type.max_size {
+    let saturation = growth_saturation(properties.size, tree_type);
+    let growth = tree_type.growth_speed * genome.growth_speed * light_response(properties.light, &tree_type.shade) * properties.vigour
+      * water_limitation(properties, tree_type) * saturation * elapsed_seconds;
+    properties.size = min(properties.size + growth, tree_type.max_size);
+  }
+}
+
+pub fn grow_with_resources(
+  properties: &mut TreeProperties,
+  tree_type: &TreeType,
+  genome: &Genome,
+  tempreture: f32,
+  soil_fertility: f32,
+  elapsed_seconds: f32
+) {
+  let effectiveness = map_to_effectiveness(tempreture, &tree_type.effectiveness);
+  let mut gain = effectiveness * soil_fertility * light_response(properties.light, &tree_type.shade) * properties.vigour * water_limitation(properties, tree_type)
+    * tree_type.resource_gain * genome.growth_speed * elapsed_seconds;
+
+  if is_growing(properties) && properties.size < tree_type.max_size {
+    let investment = gain * tree_type.growth_allocation * growth_saturation(properties.size, tree_type);
+    let growth = min(investment / tree_type.size_cost, tree_type.max_size - properties.size);
+    properties.size += growth;
+    gain -= growth * tree_type.size_cost;
+  }
+  let maintenance = tree_type.maintenance_cost * properties.size * elapsed_seconds;
+  properties.resources += gain - maintenance;
+}
+
+#[system(for_each)]
+#[allow(clippy::too_many_arguments)]
+pub fn grow_trees(
+  species: &SpeciesId,
+  properties: &mut TreeProperties,
+  position: &Position,
+  genome: &Genome,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] weather: &Weather,
+  #[resource] map: &Map,
+) {
+  let tree_type = registry.get(*species);
+  match settings.growth_model {
+    GrowthModel::Simple => grow_simple(properties, tree_type, genome, time.long.elapsed_seconds),
+    GrowthModel::ResourceBased => grow_with_resources(
+      properties,
+      tree_type,
+      genome,
+      weather.current_tempreture,
+      map.get_soil_fertility(position),
+      time.long.elapsed_seconds
+    )
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn oak() -> TreeType {
+    let registry = SpeciesRegistry::load();
+    registry.get(registry.find("oak").unwrap()).clone()
+  }
+
+  #[test]
+  fn effectiveness_is_zero_outside_of_range() {
+    let oak = oak();
+    assert_eq!(map_to_effectiveness(-20.0, &oak.effectiveness), 0.0);
+    assert_eq!(map_to_effectiveness(50.0, &oak.effectiveness), 0.0);
+    assert!(map_to_effectiveness(20.0, &oak.effectiveness) > 0.5);
+  }
+
+  #[test]
+  fn tree_grows_only_when_warm() {
+    let oak = oak();
+    let mut cold = TreeProperties::new(0.0, 1.0);
+    cold.state = TreeState::Blossom;
+    grow_with_resources(&mut cold, &oak, &Genome::default(), -10.0, 1.0, 10.0);
+    assert_eq!(cold.size, 1.0);
+
+    let mut warm = TreeProperties::new(0.0, 1.0);
+    warm.state = TreeState::Blossom;
+    grow_with_resources(&mut warm, &oak, &Genome::default(), 20.0, 1.0, 10.0);
+    assert!(warm.size > 1.0);
+    assert!(warm.resources > 20.0);
+  }
+
+  #[test]
+  fn shaded_tree_grows_slower() {
+    let oak = oak();
+    let mut sunny = TreeProperties::new(0.0, 1.0);
+    sunny.state = TreeState::Blossom;
+    let mut shaded = TreeProperties::new(0.0, 1.0);
+    shaded.state = TreeState::Blossom;
+    shaded.light = 0.3;
+    grow_with_resources(&mut sunny, &oak, &Genome::default(), 20.0, 1.0, 10.0);
+    grow_with_resources(&mut shaded, &oak, &Genome::default(), 20.0, 1.0, 10.0);
+    assert!(shaded.size < sunny.size);
+  }
+
+  #[test]
+  fn tree_does_not_outgrow_max_size() {
+    let oak = oak();
+    let mut tree = TreeProperties::new(0.0, oak.max_size - 0.01);
+    tree.state = TreeState::Ripening;
+    grow_with_resources(&mut tree, &oak, &Genome::default(), 20.0, 1.0, 1000.0);
+    assert_eq!(tree.size, oak.max_size);
+  }
+
+  #[test]
+  fn simple_growth_does_not_overshoot() {
+    let oak = oak();
+    let mut tree = TreeProperties::new(0.0, oak.max_size - 0.01);
+    tree.state = TreeState::Blossom;
+    grow_simple(&mut tree, &oak, &Genome::default(), 1000.0);
+    assert!(tree.size <= oak.max_size);
+  }
+
+  #[test]
+  fn growth_slows_down_with_size() {
+    let oak = oak();
+    let grown = |size| {
+      let mut tree = TreeProperties::new(0.0, size);
+      tree.state = TreeState::Blossom;
+      grow_with_resources(&mut tree, &oak, &Genome::default(), 20.0, 1.0, 10.0);
+      tree.size - size
+    };
+    assert!(grown(oak.max_size * 0.8) < grown(1.0));
+  }
+
+  #[test]
+  fn fast_genome_grows_faster() {
+    let oak = oak();
+    let mut average = TreeProperties::new(0.0, 1.0);
+    average.state = TreeState::Blossom;
+    let mut fast = TreeProperties::new(0.0, 1.0);
+    fast.state = TreeState::Blossom;
+    grow_with_resources(&mut average, &oak, &Genome::default(), 20.0, 1.0, 10.0);
+    grow_with_resources(&mut fast, &oak, &Genome { growth_speed: 1.5, ..Genome::default() }, 20.0, 1.0, 10.0);
+    assert!(fast.size > average.size);
+  }
+
+  #[test]
+  fn transition_waits_for_resources() {
+    let oak = oak();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    tree.resources = oak.blossom_cost / 2.0;
+    assert_eq!(pay_for_transition(&mut tree, &oak, TreeState::Blossom), TreeState::Sleep);
+
+    tree.resources = oak.blossom_cost;
+    assert_eq!(pay_for_transition(&mut tree, &oak, TreeState::Blossom), TreeState::Blossom);
+    assert_eq!(tree.resources, 0.0);
+  }
+
+  #[test]
+  fn poor_tree_skips_fruiting() {
+    let oak = oak();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    tree.resources = 0.0;
+    assert_eq!(pay_for_transition(&mut tree, &oak, TreeState::Blossom), TreeState::Sleep);
+    tree.state = pay_for_transition(&mut tree, &oak, TreeState::Ripening);
+    assert_eq!(tree.state, TreeState::Ripening);
+    assert_eq!(tree.resources, 0.0);
+
+    let mut flowering = TreeProperties::new(0.0, 1.0);
+    flowering.state = TreeState::Blossom;
+    flowering.resources = oak.ripening_cost / 2.0;
+    assert_eq!(pay_for_transition(&mut flowering, &oak, TreeState::Ripening), TreeState::Blossom);
+    flowering.resources = oak.ripening_cost;
+    assert_eq!(pay_for_transition(&mut flowering, &oak, TreeState::Ripening), TreeState::Ripening);
+    assert_eq!(flowering.resources, 0.0);
+  }
+}
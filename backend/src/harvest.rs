@@ -0,0 +1,354 @@
+use legion::*;
+use legion::world::SubWorld;
+use legion::systems::CommandBuffer;
+use rand::seq::SliceRandom;
+use serde::{ Serialize, Deserialize };
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+use geo::{ Point, Polygon };
+use geo::algorithm::contains::Contains;
+use super::timer::Timers;
+use super::settings::Settings;
+use super::map::polygon;
+use super::movements::{ Position, Vector3 };
+use super::events::{ Events, Event };
+use super::mortality::DeathCause;
+use super::random::SimRng;
+use super::scenario::Scenario;
+use super::spatial::SpatialIndex;
+use super::allometry::Dimensions;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::TreeProperties;
+
+// Ratio of stem volume to the volume of a cylinder with the same height and
+// basal area
+const FORM_FACTOR: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinningOrder {
+  #[default]
+  Random,
+  Smallest, // thinning from below
+  Largest, // thinning from above
+}
+
+fn everything() -> f32 {
+  1.0
+}
+
+// Rule choosing trees to fell. Trees matching all given conditions are
+// candidates, `share` of them is felled in the given order.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Selection {
+  #[serde(default)]
+  pub species: Option<String>,
+  #[serde(default)]
+  pub min_dbh: f32,
+  #[serde(default)]
+  pub area: Option<Vec<[f32; 2]>>,
+  #[serde(default = "everything")]
+  pub share: f32,
+  #[serde(default)]
+  pub order: ThinningOrder,
+  #[serde(skip)]
+  shape: OnceLock<Polygon<f32>>, // of the area, built on the first match
+}
+
+impl Selection {
+  pub fn matches(&self, position: &Position, species: &str, dimensions: &Dimensions) -> bool {
+    self.species.as_ref().is_none_or(|name| name == species)
+      && dimensions.dbh >= self.min_dbh
+      && self.area.as_ref().is_none_or(|area| {
+        self.shape.get_or_init(|| polygon(area)).contains(&Point::new(position.0.x, position.0.y))
+      })
+  }
+
+  // Corners of the box around the area, None when the whole map is selected
+  pub fn bounds(&self) -> Option<(Position, Position)> {
+    let area = self.area.as_ref()?;
+    let corner = |pick: fn(f32, f32) -> f32| area.iter()
+      .map(|[x, y]| Vector3::new(*x, *y, 0.0))
+      .reduce(|a, b| Vector3::new(pick(a.x, b.x), pick(a.y, b.y), 0.0))
+      .map(Position);
+    Some((corner(f32::min)?, corner(f32::max)?))
+  }
+
+  // Trees to fell out of the candidates with their DBH
+  pub fn choose(&self, candidates: Vec<(Entity, f32)>, rng: &mut SimRng) -> Vec<Entity> {
+    let mut candidates = candidates;
+    match self.order {
+      ThinningOrder::Random => candidates.shuffle(rng),
+      ThinningOrder::Smallest => candidates.sort_by(|a, b| a.1.total_cmp(&b.1)),
+      ThinningOrder::Largest => candidates.sort_by(|a, b| b.1.total_cmp(&a.1)),
+    }
+    let count = (candidates.len() as f32 * self.share.clamp(0.0, 1.0)).round() as usize;
+    candidates.into_iter().take(count).map(|(entity, _)| entity).collect()
+  }
+}
+
+// Stem volume in cubic meters
+pub fn stem_volume(dimensions: &Dimensions) -> f32 {
+  let dbh = dimensions.dbh / 100.0;
+  FORM_FACTOR * PI / 4.0 * dbh * dbh * dimensions.height
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HarvestedWood {
+  pub stems: usize,
+  pub volume: f32, // cubic meters
+  pub biomass: f32, // kilograms
+}
+
+impl HarvestedWood {
+  fn add(&mut self, dimensions: &Dimensions) {
+    self.stems += 1;
+    self.volume += stem_volume(dimensions);
+    self.biomass += dimensions.biomass;
+  }
+}
+
+// Wood removed from the stand since the start of the simulation
+#[derive(Default)]
+pub struct HarvestRecord(HashMap<SpeciesId, HarvestedWood>);
+
+impl HarvestRecord {
+  pub fn add(&mut self, species: SpeciesId, dimensions: &Dimensions) {
+    self.0.entry(species).or_default().add(dimensions);
+  }
+
+  #[cfg(test)]
+  pub fn get(&self, species: SpeciesId) -> HarvestedWood {
+    self.0.get(&species).cloned().unwrap_or_default()
+  }
+
+  pub fn total(&self) -> HarvestedWood {
+    self.0.values().fold(HarvestedWood::default(), |total, wood| HarvestedWood {
+      stems: total.stems + wood.stems,
+      volume: total.volume + wood.volume,
+      biomass: total.biomass + wood.biomass,
+    })
+  }
+}
+
+// Selection repeated on schedule, once when every_years is not given
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManagementPlan {
+  pub name: String,
+  pub start_year: f32,
+  #[serde(default)]
+  pub every_years: Option<f32>,
+  pub selection: Selection,
+}
+
+// Felling orders waiting for the next tick and scheduled plans
+pub struct Management {
+  plans: Vec<ManagementPlan>,
+  next_years: Vec<Option<f32>>,
+  years: f32, // simulated since the start
+  orders: Vec<Selection>,
+}
+
+impl Management {
+  pub fn new(scenario: &Scenario) -> Self {
+    Management {
+      plans: scenario.management.clone(),
+      next_years: scenario.management.iter().map(|plan| Some(plan.start_year)).collect(),
+      years: 0.0,
+      orders: Vec::new(),
+    }
+  }
+
+  // Fells trees matching the selection during the next tick
+  pub fn fell(&mut self, selection: Selection) {
+    self.orders.push(selection);
+  }
+
+  // Advances time, orders felling by plans which became due and takes the
+  // orders
+  fn due(&mut self, elapsed_years: f32) -> Vec<Selection> {
+    self.years += elapsed_years;
+    let mut due = Vec::new();
+    for (plan, next) in self.plans.iter().zip(self.next_years.iter_mut()) {
+      if let Some(year) = *next {
+        if self.years >= year {
+          due.push(plan.selection.clone());
+          *next = plan.every_years.map(|every| year + every);
+        }
+      }
+    }
+    for selection in due {
+      self.fell(selection);
+    }
+    self.orders.drain(..).collect()
+  }
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(SpeciesId)]
+#[read_component(Dimensions)]
+#[write_component(TreeProperties)]
+#[allow(clippy::too_many_arguments)]
+pub fn harvest(
+  world: &mut SubWorld,
+  commands: &mut CommandBuffer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] management: &mut Management,
+  #[resource] trees: &SpatialIndex<TreeProperties>,
+  #[resource] record: &mut HarvestRecord,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let mut felled: HashMap<Entity, (SpeciesId, Dimensions)> = HashMap::new();
+  for selection in management.due(time.long.elapsed_seconds / settings.seconds_in_year()) {
+    let entities: Vec<Entity> = match selection.bounds() {
+      Some((min, max)) => trees.within_rect(&min, &max).collect(),
+      None => <Entity>::query().filter(component::<TreeProperties>()).iter(world).copied().collect()
+    };
+    let mut query = <(&Position, &SpeciesId, &Dimensions, &TreeProperties)>::query();
+    let candidates: Vec<(Entity, f32)> = entities.into_iter()
+      .filter(|entity| !felled.contains_key(entity))
+      .filter_map(|entity| match query.get(world, entity) {
+        Ok((position, species, dimensions, properties))
+          if !properties.dead && selection.matches(position, &registry.get(*species).name, dimensions) =>
+            Some((entity, dimensions.dbh)),
+        _ => None
+      })
+      .collect();
+    for entity in selection.choose(candidates, rng) {
+      if let Ok(entry) = world.entry_ref(entity) {
+        if let (Ok(species), Ok(dimensions)) = (entry.get_component::<SpeciesId>(), entry.get_component::<Dimensions>()) {
+          felled.insert(entity, (*species, dimensions.clone()));
+        }
+      }
+    }
+  }
+
+  for (entity, (species, dimensions)) in felled {
+    if let Ok(mut entry) = world.entry_mut(entity) {
+      if let Ok(properties) = entry.get_component_mut::<TreeProperties>() {
+        properties.dead = true;
+      }
+    }
+    record.add(species, &dimensions);
+    events.emit(Event::EntityDied { entity, cause: DeathCause::Harvested });
+    commands.remove(entity);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::movements::Vector3;
+  use super::super::tree::place_tree_test;
+  use super::super::spatial::update_spatial_index_system;
+
+  fn point(x: f32, y: f32) -> Position {
+    Position(Vector3::new(x, y, 0.0))
+  }
+
+  fn selection(yaml: &str) -> Selection {
+    serde_yaml::from_str(yaml).unwrap()
+  }
+
+  fn stand() -> (World, Resources, Schedule) {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    for (i, size) in [1.0, 2.0, 3.0, 4.0].iter().enumerate() {
+      place_tree_test(&mut world, &registry, "oak", point(i as f32 * 5.0, 0.0), 0.0, *size).unwrap();
+    }
+    place_tree_test(&mut world, &registry, "hazel", point(0.0, 5.0), 0.0, 3.0).unwrap();
+    let mut resources = Resources::default();
+    resources.insert(Timers::default());
+    resources.insert(Settings::default());
+    resources.insert(registry);
+    resources.insert(Management::new(&Scenario::default()));
+    resources.insert(SpatialIndex::<TreeProperties>::new(5.0));
+    resources.insert(HarvestRecord::default());
+    resources.insert(SimRng::seeded(1));
+    resources.insert(Events::default());
+    let schedule = Schedule::builder()
+      .add_system(update_spatial_index_system::<TreeProperties>())
+      .add_system(harvest_system())
+      .build();
+    (world, resources, schedule)
+  }
+
+  fn sizes(world: &World) -> Vec<f32> {
+    let mut query = <&TreeProperties>::query();
+    let mut sizes: Vec<f32> = query.iter(world).map(|properties| properties.size).collect();
+    sizes.sort_by(|a, b| a.total_cmp(b));
+    sizes
+  }
+
+  #[test]
+  fn selection_matches_species_size_and_area() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.get(registry.find("oak").unwrap());
+    let big = Dimensions::of(&TreeProperties::new(0.0, 5.0), oak);
+    let rule = selection("{ species: oak, min_dbh: 20, area: [[0, 0], [10, 0], [10, 10], [0, 10]] }");
+    assert!(rule.matches(&point(5.0, 5.0), "oak", &big));
+    assert!(!rule.matches(&point(15.0, 5.0), "oak", &big));
+    assert!(!rule.matches(&point(5.0, 5.0), "hazel", &big));
+    let small = Dimensions::of(&TreeProperties::new(0.0, 1.0), oak);
+    assert!(!rule.matches(&point(5.0, 5.0), "oak", &small));
+    assert!(selection("{}").matches(&point(100.0, 0.0), "hazel", &small));
+  }
+
+  #[test]
+  fn thinning_from_below_fells_smallest_trees() {
+    let (mut world, mut resources, mut schedule) = stand();
+    resources.get_mut::<Management>().unwrap().fell(selection("{ species: oak, share: 0.5, order: smallest }"));
+    schedule.execute(&mut world, &mut resources);
+    assert_eq!(sizes(&world), vec!(3.0, 3.0, 4.0));
+
+    let registry = resources.get::<SpeciesRegistry>().unwrap();
+    let record = resources.get::<HarvestRecord>().unwrap();
+    let oak = record.get(registry.find("oak").unwrap());
+    assert_eq!(oak.stems, 2);
+    assert!(oak.volume > 0.0);
+    assert!(oak.biomass > 0.0);
+    assert_eq!(record.get(registry.find("hazel").unwrap()).stems, 0);
+    assert_eq!(record.total(), oak);
+    let events = resources.get::<Events>().unwrap();
+    assert_eq!(events.iter().filter(|event| matches!(event, Event::EntityDied { cause: DeathCause::Harvested, .. })).count(), 2);
+  }
+
+  #[test]
+  fn felling_in_area_leaves_trees_outside() {
+    let (mut world, mut resources, mut schedule) = stand();
+    let rule = selection("{ area: [[4, -1], [11, -1], [11, 1], [4, 1]] }");
+    assert_eq!(rule.bounds(), Some((point(4.0, -1.0), point(11.0, 1.0))));
+    resources.get_mut::<Management>().unwrap().fell(rule);
+    schedule.execute(&mut world, &mut resources);
+    assert_eq!(sizes(&world), vec!(1.0, 3.0, 4.0));
+  }
+
+  #[test]
+  fn plans_repeat_on_schedule() {
+    let scenario: Scenario = serde_yaml::from_str("
+      management:
+        - name: clearcut
+          start_year: 2
+          every_years: 3
+          selection: { order: largest, share: 0.5 }
+    ").unwrap();
+    let mut management = Management::new(&scenario);
+    let due: Vec<usize> = (0..9).map(|_| management.due(1.0).len()).collect();
+    assert_eq!(due, vec!(0, 1, 0, 0, 1, 0, 0, 1, 0));
+    assert!(Management::new(&Scenario::load()).plans.iter().any(|plan| plan.every_years.is_some()));
+  }
+
+  #[test]
+  fn stem_volume_grows_with_tree() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.get(registry.find("oak").unwrap());
+    let volume = |size| stem_volume(&Dimensions::of(&TreeProperties::new(0.0, size), oak));
+    assert_eq!(volume(0.0), 0.0);
+    assert!(volume(2.0) < volume(5.0));
+  }
+}
@@ -0,0 +1,379 @@
+use legion::*;
+use legion::world::SubWorld;
+use serde::{ Serialize, Deserialize };
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+use geo::{ Point, Polygon };
+use geo::algorithm::area::Area;
+use geo::algorithm::contains::Contains;
+use super::timer::Timers;
+use super::settings::Settings;
+use super::map::{ Map, polygon };
+use super::movements::Position;
+use super::allometry::Dimensions;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::TreeProperties;
+
+const SQUARE_METERS_IN_HECTARE: f32 = 10000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+  Csv,
+  Json,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InventoryArea {
+  pub name: String,
+  pub area: Vec<[f32; 2]>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InventoryConfig {
+  pub every_years: f32,
+  pub height_class: f32, // meters
+  pub age_class: f32, // years
+  #[serde(default)]
+  pub min_dbh: f32, // centimeters, thinner stems are not counted
+  pub format: ReportFormat, // of reports written to the output directory
+  #[serde(default)]
+  pub areas: Vec<InventoryArea>, // reported separately besides the whole map
+}
+
+// Counts of values in classes of the same width starting from zero
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Histogram {
+  pub width: f32,
+  pub counts: Vec<usize>,
+}
+
+impl Histogram {
+  fn of(values: impl Iterator<Item = f32>, width: f32) -> Self {
+    let mut counts: Vec<usize> = Vec::new();
+    for value in values {
+      let class = (value.max(0.0) / width) as usize;
+      if class >= counts.len() {
+        counts.resize(class + 1, 0);
+      }
+      counts[class] += 1;
+    }
+    Histogram { width, counts }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SpeciesShare {
+  pub stems: usize,
+  pub basal_area: f32, // square meters per hectare
+}
+
+// Standard forestry metrics of trees in the area. Diameters are in
+// centimeters at breast height.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct InventoryReport {
+  pub name: String,
+  pub year: f32,
+  pub area: f32, // hectares
+  pub stems: usize,
+  pub density: f32, // stems per hectare
+  pub basal_area: f32, // square meters per hectare
+  pub mean_dbh: f32,
+  pub quadratic_mean_dbh: f32,
+  pub mean_height: f32,
+  pub heights: Histogram,
+  pub age_classes: Histogram,
+  pub species: BTreeMap<String, SpeciesShare>,
+}
+
+#[derive(Clone, Copy)]
+pub struct InventoryTree<'a> {
+  pub species: &'a str,
+  pub age_years: f32,
+  pub dimensions: &'a Dimensions,
+}
+
+// Basal area of a stem in square meters
+pub fn basal_area(dimensions: &Dimensions) -> f32 {
+  let dbh = dimensions.dbh / 100.0;
+  PI / 4.0 * dbh * dbh
+}
+
+pub fn inventory(name: &str, year: f32, area: f32, trees: &[InventoryTree], config: &InventoryConfig) -> InventoryReport {
+  let hectares = area / SQUARE_METERS_IN_HECTARE;
+  let per_hectare = |value: f32| if hectares > 0.0 { value / hectares } else { 0.0 };
+  let stems = trees.len();
+  let mean = |f: fn(&InventoryTree) -> f32| if stems > 0 { trees.iter().map(f).sum::<f32>() / stems as f32 } else { 0.0 };
+
+  let mut species: BTreeMap<String, SpeciesShare> = BTreeMap::new();
+  for tree in trees {
+    let share = species.entry(tree.species.to_string()).or_default();
+    share.stems += 1;
+    share.basal_area += basal_area(tree.dimensions);
+  }
+  for share in species.values_mut() {
+    share.basal_area = per_hectare(share.basal_area);
+  }
+
+  InventoryReport {
+    name: name.to_string(),
+    year,
+    area: hectares,
+    stems,
+    density: per_hectare(stems as f32),
+    basal_area: per_hectare(trees.iter().map(|tree| basal_area(tree.dimensions)).sum()),
+    mean_dbh: mean(|tree| tree.dimensions.dbh),
+    quadratic_mean_dbh: mean(|tree| tree.dimensions.dbh * tree.dimensions.dbh).sqrt(),
+    mean_height: mean(|tree| tree.dimensions.height),
+    heights: Histogram::of(trees.iter().map(|tree| tree.dimensions.height), config.height_class),
+    age_classes: Histogram::of(trees.iter().map(|tree| tree.age_years), config.age_class),
+    species,
+  }
+}
+
+impl InventoryReport {
+  // Rows of year,name,metric,class,value. Class is empty for scalar metrics,
+  // lower bound of the class for histograms and species name for composition.
+  pub fn csv_rows(&self) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut row = |metric: &str, class: String, value: String| {
+      rows.push(format!("{},{},{},{},{}", self.year, self.name, metric, class, value));
+    };
+    row("area", String::new(), self.area.to_string());
+    row("stems", String::new(), self.stems.to_string());
+    row("density", String::new(), self.density.to_string());
+    row("basal_area", String::new(), self.basal_area.to_string());
+    row("mean_dbh", String::new(), self.mean_dbh.to_string());
+    row("quadratic_mean_dbh", String::new(), self.quadratic_mean_dbh.to_string());
+    row("mean_height", String::new(), self.mean_height.to_string());
+    for (metric, histogram) in [("height_class", &self.heights), ("age_class", &self.age_classes)] {
+      for (class, count) in histogram.counts.iter().enumerate() {
+        row(metric, (class as f32 * histogram.width).to_string(), count.to_string());
+      }
+    }
+    for (name, share) in &self.species {
+      row("species_stems", name.clone(), share.stems.to_string());
+      row("species_basal_area", name.clone(), share.basal_area.to_string());
+    }
+    rows
+  }
+}
+
+pub fn to_csv(reports: &[InventoryReport]) -> String {
+  let mut csv = String::from("year,name,metric,class,value\n");
+  for row in reports.iter().flat_map(|report| report.csv_rows()) {
+    csv.push_str(&row);
+    csv.push('\n');
+  }
+  csv
+}
+
+pub fn to_json(reports: &[InventoryReport]) -> String {
+  serde_json::to_string_pretty(reports).unwrap()
+}
+
+// Inventories taken at configured intervals
+pub struct Inventories {
+  config: Option<InventoryConfig>,
+  years: f32, // simulated since the start
+  next_year: f32,
+}
+
+impl Inventories {
+  pub fn new(config: Option<InventoryConfig>) -> Self {
+    Inventories { config, years: 0.0, next_year: 0.0 }
+  }
+}
+
+fn map_area(map: &Map) -> f32 {
+  let (min, max) = map.bounds();
+  (max.x - min.x) * (max.y - min.y)
+}
+
+fn contains(area: &Polygon<f32>, position: &Position) -> bool {
+  area.contains(&Point::new(position.0.x, position.0.y))
+}
+
+fn write_reports(directory: &str, year: f32, format: ReportFormat, reports: &[InventoryReport]) {
+  let (extension, content) = match format {
+    ReportFormat::Csv => ("csv", to_csv(reports)),
+    ReportFormat::Json => ("json", to_json(reports)),
+  };
+  let path = Path::new(directory).join(format!("inventory_{}.{}", year.round(), extension));
+  if let Err(error) = fs::create_dir_all(directory).and_then(|_| fs::write(&path, content)) {
+    println!("cannot write inventory to {:?}: {}", path, error);
+  }
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(SpeciesId)]
+#[read_component(TreeProperties)]
+#[read_component(Dimensions)]
+pub fn report_inventory(
+  world: &mut SubWorld,
+  #[resource] inventories: &mut Inventories,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] map: &Map,
+) {
+  inventories.years += time.long.elapsed_seconds / settings.seconds_in_year();
+  let config = match &inventories.config {
+    Some(config) if inventories.years >= inventories.next_year => config.clone(),
+    _ => return
+  };
+  let year = inventories.years;
+  while inventories.next_year <= year {
+    inventories.next_year += config.every_years.max(f32::EPSILON);
+  }
+
+  // Herbs and stems below breast height or min_dbh are left out
+  let mut query = <(&Position, &SpeciesId, &TreeProperties, &Dimensions)>::query();
+  let trees: Vec<(Position, InventoryTree)> = query.iter(world)
+    .filter(|(_, species, _, dimensions)| {
+      registry.get(**species).life_form.is_woody() && dimensions.dbh > 0.0 && dimensions.dbh >= config.min_dbh
+    })
+    .map(|(position, species, properties, dimensions)| (*position, InventoryTree {
+      species: &registry.get(*species).name,
+      age_years: properties.age / settings.seconds_in_year(),
+      dimensions,
+    }))
+    .collect();
+
+  let mut reports = Vec::new();
+  let all: Vec<InventoryTree> = trees.iter().map(|(_, tree)| *tree).collect();
+  reports.push(inventory("stand", year, map_area(map), &all, &config));
+  for area in &config.areas {
+    let shape = polygon(&area.area);
+    let inside: Vec<InventoryTree> = trees.iter()
+      .filter(|(position, _)| contains(&shape, position))
+      .map(|(_, tree)| *tree)
+      .collect();
+    reports.push(inventory(&area.name, year, shape.unsigned_area(), &inside, &config));
+  }
+
+  if let Some(directory) = &settings.output_directory {
+    write_reports(directory, year, config.format, &reports);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::movements::Vector3;
+  use super::super::tree::place_tree_test;
+
+  fn config() -> InventoryConfig {
+    InventoryConfig {
+      every_years: 5.0,
+      height_class: 5.0,
+      age_class: 10.0,
+      min_dbh: 7.0,
+      format: ReportFormat::Csv,
+      areas: Vec::new(),
+    }
+  }
+
+  fn stem(dbh: f32, height: f32) -> Dimensions {
+    Dimensions { dbh, height, ..Dimensions::default() }
+  }
+
+  #[test]
+  fn plot_metrics_are_per_hectare() {
+    let (small, large) = (stem(30.0, 12.0), stem(40.0, 22.0));
+    let trees = [
+      InventoryTree { species: "oak", age_years: 5.0, dimensions: &small },
+      InventoryTree { species: "hazel", age_years: 25.0, dimensions: &large },
+    ];
+    let report = inventory("plot", 1.0, 5000.0, &trees, &config());
+    assert_eq!(report.area, 0.5);
+    assert_eq!(report.stems, 2);
+    assert_eq!(report.density, 4.0);
+    assert!((report.basal_area - PI / 4.0 * (0.09 + 0.16) * 2.0).abs() < 1e-5);
+    assert_eq!(report.mean_dbh, 35.0);
+    assert!((report.quadratic_mean_dbh - 1250.0f32.sqrt()).abs() < 1e-4);
+    assert_eq!(report.mean_height, 17.0);
+    assert_eq!(report.heights.counts, vec![0, 0, 1, 0, 1]);
+    assert_eq!(report.age_classes.counts, vec![1, 0, 1]);
+    assert_eq!(report.species["oak"].stems, 1);
+    assert!((report.species["hazel"].basal_area - PI / 4.0 * 0.16 * 2.0).abs() < 1e-5);
+
+    let empty = inventory("plot", 1.0, 5000.0, &[], &config());
+    assert_eq!(empty.stems, 0);
+    assert_eq!(empty.mean_dbh, 0.0);
+    assert!(empty.heights.counts.is_empty());
+  }
+
+  #[test]
+  fn reports_are_exported() {
+    let tree = stem(20.0, 7.0);
+    let report = inventory("plot", 10.0, 10000.0, &[InventoryTree { species: "oak", age_years: 12.0, dimensions: &tree }], &config());
+    let csv = to_csv(std::slice::from_ref(&report));
+    assert!(csv.starts_with("year,name,metric,class,value\n"));
+    assert!(csv.contains("10,plot,stems,,1\n"));
+    assert!(csv.contains("10,plot,height_class,5,1\n"));
+    assert!(csv.contains("10,plot,age_class,10,1\n"));
+    assert!(csv.contains("10,plot,species_stems,oak,1\n"));
+    let json: serde_json::Value = serde_json::from_str(&to_json(&[report])).unwrap();
+    assert_eq!(json[0]["stems"], 1);
+    assert_eq!(json[0]["species"]["oak"]["stems"], 1);
+    assert_eq!(json[0]["heights"]["counts"][1], 1);
+  }
+
+  #[test]
+  fn inventory_is_taken_at_intervals() {
+    let directory = std::env::temp_dir().join("inventory_test");
+    let _ = fs::remove_dir_all(&directory);
+    let settings = Settings { output_directory: Some(directory.to_string_lossy().into()), ..Settings::default() };
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    for x in [-20.0, -10.0, 10.0, 20.0] {
+      place_tree_test(&mut world, &registry, "oak", Position(Vector3::new(x, 0.0, 0.0)), 0.0, 5.0).unwrap();
+    }
+    place_tree_test(&mut world, &registry, "hazel", Position(Vector3::new(0.0, 30.0, 0.0)), 0.0, 5.0).unwrap();
+    // neither herbs nor thin seedlings are stems
+    place_tree_test(&mut world, &registry, "nettle", Position(Vector3::new(15.0, 0.0, 0.0)), 0.0, 5.0).unwrap();
+    place_tree_test(&mut world, &registry, "oak", Position(Vector3::new(25.0, 0.0, 0.0)), 0.0, 1.0).unwrap();
+    place_tree_test(&mut world, &registry, "oak", Position(Vector3::new(30.0, 0.0, 0.0)), 0.0, 0.0).unwrap();
+    let config = InventoryConfig {
+      format: ReportFormat::Json,
+      areas: vec![InventoryArea { name: "east".into(), area: vec![[0.0, -50.0], [50.0, -50.0], [50.0, 50.0], [0.0, 50.0]] }],
+      ..config()
+    };
+
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = settings.seconds_in_year();
+    let mut resources = Resources::default();
+    resources.insert(Inventories::new(Some(config)));
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(registry);
+    resources.insert(Map::test_square(100.0, 100.0));
+    let mut schedule = Schedule::builder()
+      .add_system(report_inventory_system())
+      .build();
+    for _ in 0..10 {
+      schedule.execute(&mut world, &mut resources);
+    }
+
+    let mut files: Vec<String> = fs::read_dir(&directory).unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().into())
+      .collect();
+    files.sort();
+    assert_eq!(files, vec!["inventory_1.json", "inventory_10.json", "inventory_5.json"]);
+    let json = fs::read_to_string(directory.join("inventory_5.json")).unwrap();
+    let reports: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let (stand, east) = (&reports[0], &reports[1]);
+    assert_eq!(stand["year"].as_f64().unwrap().round(), 5.0);
+    assert_eq!(stand["area"], 1.0);
+    assert_eq!(stand["stems"], 5);
+    assert_eq!(stand["species"]["oak"]["stems"], 4);
+    assert_eq!(east["name"], "east");
+    assert_eq!(east["area"], 0.5);
+    assert_eq!(east["stems"], 2);
+    let _ = fs::remove_dir_all(&directory);
+  }
+}
@@ -0,0 +1,194 @@
+use legion::*;
+use legion::systems::CommandBuffer;
+use rand::Rng;
+use serde::{ Serialize, Deserialize };
+use std::f32::consts::PI;
+use super::timer::Timers;
+use super::settings::Settings;
+use super::map::Map;
+use super::movements::{ Position, Vector3 };
+use super::events::{ Events, Event };
+use super::genetics::Genome;
+use super::random::{ SimRng, random_count };
+use super::spatial::SpatialIndex;
+use super::grass::GrassLayer;
+use super::succession::can_germinate;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, TreeState, place_tree };
+
+// Life history of a species. All life forms share phenology, growth and
+// dispersal, they differ in what happens around dormancy and seeding.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "form", rename_all = "snake_case")]
+pub enum LifeForm {
+  Tree,
+  // woody plant with several stems, usually spreading by sprouts
+  Shrub,
+  // herb whose above-ground part dies back in winter, roots survive
+  Perennial {
+    dieback: f32 // part of size lost when going to sleep
+  },
+  // herb which dies after it has seeded in its first year
+  Annual,
+}
+
+impl LifeForm {
+  pub fn dies_after_seeding(&self) -> bool {
+    matches!(self, LifeForm::Annual)
+  }
+
+  // Trees and shrubs, which grow stems counted by inventories
+  pub fn is_woody(&self) -> bool {
+    matches!(self, LifeForm::Tree | LifeForm::Shrub)
+  }
+}
+
+// New shoots sprouting from roots around the plant
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClonalSpread {
+  pub yearly_rate: f32, // expected amount of new shoots per year awake
+  pub distance: f32, // max distance of a new shoot from the parent
+}
+
+pub fn die_back(properties: &mut TreeProperties, tree_type: &TreeType) {
+  if let LifeForm::Perennial { dieback } = tree_type.life_form {
+    properties.size *= 1.0 - dieback;
+  }
+}
+
+fn sprout_position<R: Rng>(position: &Position, spread: &ClonalSpread, map: &Map, rng: &mut R) -> Option<Position> {
+  let distance = spread.distance * rng.gen::<f32>().sqrt();
+  let angle = rng.gen_range(0.0..2.0 * PI);
+  let sprout = Position(position.0 + Vector3::new(distance * angle.cos(), distance * angle.sin(), 0.0));
+  match map.ground_type(&sprout) {
+    Some(ground) if ground.moisture() > 0.0 => Some(sprout),
+    _ => None
+  }
+}
+
+// Mature awake plants sprout genetically identical shoots nearby, unless the
+// place is already too dense or too dark for the species to germinate
+#[system(for_each)]
+#[allow(clippy::too_many_arguments)]
+pub fn clonal_spread(
+  entity: &Entity,
+  position: &Position,
+  species: &SpeciesId,
+  properties: &TreeProperties,
+  genome: &Genome,
+  command_buffer: &mut CommandBuffer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] map: &Map,
+  #[resource] grass: &GrassLayer,
+  #[resource] trees: &SpatialIndex<TreeProperties>,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let tree_type = registry.get(*species);
+  let spread = match &tree_type.clonal_spread {
+    Some(spread) => spread,
+    None => return
+  };
+  let years = properties.age / settings.seconds_in_year();
+  if years <= tree_type.maturity_years || properties.state == TreeState::Sleep || properties.dead {
+    return;
+  }
+  let elapsed_years = time.long.elapsed_seconds / settings.seconds_in_year();
+  for _ in 0..random_count(spread.yearly_rate * elapsed_years, rng) {
+    if let Some(sprout) = sprout_position(position, spread, map, rng) {
+      let crowded = trees.within_radius(&sprout, tree_type.germination_radius).count() >= tree_type.germination_max_density;
+      if !crowded && can_germinate(tree_type, grass.light_at(&sprout)) {
+        let shoot = place_tree(command_buffer, sprout, *species, genome.clone());
+        events.emit(Event::PlantSprouted { parent: *entity, entity: shoot, position: sprout });
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::grass::GrassConfig;
+  use num::traits::Zero;
+  use super::super::tree::place_tree_test;
+
+  #[test]
+  fn perennials_die_back_in_winter() {
+    let registry = SpeciesRegistry::load();
+    let nettle = registry.get(registry.find("nettle").unwrap());
+    let oak = registry.get(registry.find("oak").unwrap());
+    let mut herb = TreeProperties::new(0.0, 1.0);
+    die_back(&mut herb, nettle);
+    assert!(herb.size < 1.0);
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    die_back(&mut tree, oak);
+    assert_eq!(tree.size, 1.0);
+    assert!(registry.get(registry.find("poppy").unwrap()).life_form.dies_after_seeding());
+    assert!(!oak.life_form.dies_after_seeding());
+  }
+
+  // Ripening plant of the species alone on the map, with ticks of the given
+  // length running clonal spread
+  fn spreading(name: &str, size: f32, years_per_tick: f32) -> (World, Resources, Schedule, Entity) {
+    let registry = SpeciesRegistry::load();
+    let settings = Settings::default();
+    let mut world = World::default();
+    let parent = place_tree_test(&mut world, &registry, name, Position::zero(), 10.0 * settings.seconds_in_year(), size).unwrap();
+    world.entry(parent).unwrap().get_component_mut::<TreeProperties>().unwrap().state = TreeState::Ripening;
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = years_per_tick * settings.seconds_in_year();
+
+    let mut resources = Resources::default();
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(registry);
+    let map = Map::test_square(20.0, 20.0);
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(map);
+    resources.insert(SpatialIndex::<TreeProperties>::new(2.0));
+    resources.insert(SimRng::seeded(1));
+    resources.insert(Events::default());
+    let schedule = Schedule::builder()
+      .add_system(clonal_spread_system())
+      .build();
+    (world, resources, schedule, parent)
+  }
+
+  fn yearly_rate(name: &str) -> f32 {
+    let registry = SpeciesRegistry::load();
+    registry.get(registry.find(name).unwrap()).clonal_spread.as_ref().unwrap().yearly_rate
+  }
+
+  #[test]
+  fn shrubs_spread_by_sprouts() {
+    // long enough for exactly one shoot
+    let (mut world, mut resources, mut schedule, parent) = spreading("hazel", 3.0, 1.0 / yearly_rate("hazel"));
+    schedule.execute(&mut world, &mut resources);
+
+    let hazel = resources.get::<SpeciesRegistry>().unwrap().find("hazel").unwrap();
+    let mut query = <(&SpeciesId, &Genome)>::query();
+    let plants: Vec<(&SpeciesId, &Genome)> = query.iter(&world).collect();
+    assert_eq!(plants.len(), 2);
+    assert!(plants.iter().all(|(species, genome)| **species == hazel && **genome == Genome::default()));
+    let events = resources.get::<Events>().unwrap();
+    assert!(matches!(events.iter().next(), Some(Event::PlantSprouted { parent: p, .. }) if *p == parent));
+  }
+
+  #[test]
+  fn shoots_follow_yearly_rate_over_short_ticks() {
+    let rate = yearly_rate("nettle");
+    let (ticks, years_per_tick) = (10000, 0.01);
+    let (mut world, mut resources, mut schedule, _) = spreading("nettle", 1.0, years_per_tick);
+    let mut shoots = 0;
+    for _ in 0..ticks {
+      schedule.execute(&mut world, &mut resources);
+      let mut events = resources.get_mut::<Events>().unwrap();
+      shoots += events.iter().filter(|event| matches!(event, Event::PlantSprouted { .. })).count();
+      events.clear();
+    }
+    let yearly = shoots as f32 / (ticks as f32 * years_per_tick);
+    assert!((yearly - rate).abs() < 0.3 * rate, "{} shoots per year", yearly);
+  }
+}
@@ -0,0 +1,128 @@
+use legion::*;
+use legion::world::SubWorld;
+use std::collections::HashMap;
+use super::movements::Position;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::spatial::{ SpatialIndex, distance_2d };
+use super::tree::{ TreeProperties, clamp, max, min };
+use super::allometry::{ crown_radius, height };
+
+pub struct Crown {
+  pub position: Position,
+  pub radius: f32,
+  pub height: f32,
+  pub opacity: f32,
+}
+
+// Part of crown `a` covered by crown `b`, approximated by depth of overlap.
+// Seedlings without a crown yet are points, fully shaded under `b`.
+fn overlap(a: &Crown, b: &Crown) -> f32 {
+  let distance = distance_2d(&a.position.0, &b.position.0);
+  let depth = a.radius + b.radius - distance;
+  let smallest = min(a.radius, b.radius);
+  if a.radius <= 0.0 {
+    if distance < b.radius { 1.0 } else { 0.0 }
+  } else if depth <= 0.0 || smallest <= 0.0 {
+    0.0
+  } else {
+    let coverage = min(1.0, (b.radius / a.radius).powi(2));
+    clamp(depth / (2.0 * smallest), 0.0, 1.0) * coverage
+  }
+}
+
+// Light availability for every crown: 1.0 - full sun, 0.0 - full shade.
+// Only taller neighbours cast shade.
+pub fn compute_light(crowns: &HashMap<Entity, Crown>, index: &SpatialIndex<TreeProperties>) -> HashMap<Entity, f32> {
+  let max_radius = crowns.values().fold(0.0, |acc, crown| max(acc, crown.radius));
+
+  crowns.iter().map(|(entity, crown)| {
+    let light = index.within_radius(&crown.position, crown.radius + max_radius)
+      .filter(|other| other != entity)
+      .filter_map(|other| crowns.get(&other))
+      .filter(|other| other.height > crown.height)
+      .fold(1.0, |light, other| light * (1.0 - other.opacity * overlap(crown, other)));
+    (*entity, light)
+  }).collect()
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(SpeciesId)]
+#[write_component(TreeProperties)]
+pub fn update_light(
+  world: &mut SubWorld,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] index: &SpatialIndex<TreeProperties>
+) {
+  let mut query = <(Entity, &Position, &SpeciesId, &TreeProperties)>::query();
+  let crowns: HashMap<Entity, Crown> = query.iter(world).map(|(entity, position, species, properties)| {
+    let tree_type = registry.get(*species);
+    (*entity, Crown {
+      position: *position,
+      radius: crown_radius(properties, tree_type),
+      height: height(properties, tree_type),
+      opacity: tree_type.crown_opacity,
+    })
+  }).collect();
+
+  let light = compute_light(&crowns, index);
+  let mut query = <(Entity, &mut TreeProperties)>::query();
+  for (entity, properties) in query.iter_mut(world) {
+    if let Some(light) = light.get(entity) {
+      properties.light = *light;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::movements::Vector3;
+
+  // Returns light of every tree in order of (x, radius, height) descriptions
+  fn light_of(trees: &[(f32, f32, f32)]) -> Vec<f32> {
+    let mut world = World::default();
+    let mut index = SpatialIndex::new(2.0);
+    let mut crowns = HashMap::new();
+    let entities: Vec<Entity> = trees.iter().map(|&(x, radius, height)| {
+      let position = Position(Vector3::new(x, 0.0, 0.0));
+      let entity = world.push((position,));
+      index.insert(entity, &position);
+      crowns.insert(entity, Crown { position, radius, height, opacity: 0.8 });
+      entity
+    }).collect();
+    let light = compute_light(&crowns, &index);
+    entities.iter().map(|entity| light[entity]).collect()
+  }
+
+  #[test]
+  fn lonely_tree_gets_full_light() {
+    assert_eq!(light_of(&[(0.0, 2.0, 10.0)]), vec!(1.0));
+  }
+
+  #[test]
+  fn taller_neighbour_shades_smaller_tree() {
+    let light = light_of(&[(0.0, 3.0, 10.0), (1.0, 1.0, 3.0)]);
+    assert_eq!(light[0], 1.0);
+    assert!(light[1] < 0.5);
+  }
+
+  #[test]
+  fn seedling_under_crown_is_shaded() {
+    let light = light_of(&[(0.0, 4.0, 12.0), (1.0, 0.0, 0.0), (6.0, 0.0, 0.0)]);
+    assert!((light[1] - 0.2).abs() < 1e-6);
+    assert_eq!(light[2], 1.0);
+  }
+
+  #[test]
+  fn distant_trees_do_not_compete() {
+    assert_eq!(light_of(&[(0.0, 3.0, 10.0), (10.0, 1.0, 3.0)]), vec!(1.0, 1.0));
+  }
+
+  #[test]
+  fn shade_of_several_neighbours_accumulates() {
+    let one = light_of(&[(0.0, 1.0, 2.0), (1.5, 1.0, 5.0)]);
+    let two = light_of(&[(0.0, 1.0, 2.0), (1.5, 1.0, 5.0), (-1.5, 1.0, 5.0)]);
+    assert!(two[0] < one[0]);
+  }
+}
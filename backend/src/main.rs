@@ -11,6 +11,33 @@ mod settings;
 mod tree;
 mod movements;
 mod interpolations;
+mod events;
+mod species;
+mod growth;
+mod mortality;
+mod random;
+mod spatial;
+mod light;
+mod dispersal;
+mod fruit;
+mod raster;
+mod seed_bank;
+mod fruit_layer;
+mod genetics;
+mod allometry;
+mod phenology;
+mod grass;
+mod life_form;
+mod disease;
+mod fire;
+mod scenario;
+mod harvest;
+mod planting;
+mod carbon;
+mod drought;
+mod pollination;
+mod inventory;
+mod succession;
 
 use timer::{
   Timers,
@@ -56,23 +83,54 @@ async fn executor(
 async fn main() {
   let mut w = World::default();
   let mut resources = Resources::default();
-  let settings = settings::Settings{
-    days_in_season: 2,
-    day_duration: 60.0,
-    climate_zone: "moderate".into()
-  };
+  let settings = settings::Settings::load();
   let (_tx, rx) = unbounded();
 
   let seconds_in_year = settings.seconds_in_year();
+  let scenario = scenario::Scenario::load();
 
   resources.insert(climate::Weather::prepare(&settings.climate_zone));
+  resources.insert(settings.seed.map(random::SimRng::seeded).unwrap_or_default());
   resources.insert(settings);
-  resources.insert(map::Map::test_square(10., 20.));
+  let map = map::Map::test_square(10., 20.);
+  resources.insert(seed_bank::SeedBank::covering(&map, 1.0));
+  resources.insert(fruit_layer::FruitLayer::covering(&map, 1.0));
+  resources.insert(grass::GrassLayer::covering(&map, 1.0, grass::GrassConfig::load()));
+  let mut fire = fire::FireLayer::covering(&map, 2.0, fire::FireConfig::load());
+  fire.plan(&scenario.ignitions);
+  resources.insert(fire);
+  resources.insert(carbon::CarbonAccounts::covering(&map, 1.0, carbon::CarbonConfig::load()));
+  resources.insert(map);
   resources.insert(Timers::default());
+  resources.insert(events::Events::default());
+  resources.insert(genetics::GenomeMetrics::default());
+  resources.insert(drought::WaterConfig::load());
+  resources.insert(disease::Pathogens::load());
+  resources.insert(disease::OutbreakMetrics::default());
+  resources.insert(harvest::Management::new(&scenario));
+  resources.insert(harvest::HarvestRecord::default());
+  resources.insert(inventory::Inventories::new(scenario.inventory.clone()));
+  resources.insert(spatial::SpatialIndex::<tree::TreeProperties>::new(5.0));
+  resources.insert(spatial::SpatialIndex::<fruit::Fruit>::new(5.0));
+  resources.insert(spatial::SpatialIndex::<mortality::Deadwood>::new(5.0));
 
   use movements::Position;
 
-  tree::place_tree_test(&mut w, Position::zero(), 5.0*seconds_in_year, 10.0);
+  let species = species::SpeciesRegistry::load();
+  if let Err(error) = tree::place_tree_test(&mut w, &species, "oak", Position::zero(), 5.0*seconds_in_year, 10.0) {
+    println!("cannot place the first tree: {}", error);
+  }
+  {
+    let map = resources.get::<map::Map>().unwrap();
+    let settings = resources.get::<settings::Settings>().unwrap();
+    let mut rng = resources.get_mut::<random::SimRng>().unwrap();
+    for plan in &scenario.planting {
+      if let Err(error) = planting::plant(&mut w, &species, plan, &map, &settings, &mut *rng) {
+        println!("cannot plant {}: {}", plan.species, error);
+      }
+    }
+  }
+  resources.insert(species);
 
   let fast_scheduler = Schedule::builder()
     .add_system(update_positions_system())
@@ -80,12 +138,41 @@ async fn main() {
     ;
 
   let slow_scheduler = Schedule::builder()
+    .add_system(events::clear_events_system())
     .add_system(timer::timer_update_system())
     .add_system(climate::weather_system())
     .flush()
+    .add_system(spatial::update_spatial_index_system::<tree::TreeProperties>())
+    .add_system(spatial::update_spatial_index_system::<fruit::Fruit>())
+    .add_system(spatial::update_spatial_index_system::<mortality::Deadwood>())
+    .add_system(light::update_light_system())
+    .add_system(grass::update_grass_system())
+    .add_system(drought::update_water_system())
+    .add_system(pollination::pollinate_system())
     .add_system(tree::update_trees_system())
+    .add_system(growth::grow_trees_system())
+    .add_system(succession::update_shade_stress_system())
+    .add_system(allometry::update_dimensions_system())
+    .add_system(mortality::mortality_system())
+    .add_system(disease::spread_disease_system())
+    .add_system(disease::progress_disease_system())
+    .add_system(fire::update_fire_system())
+    .add_system(fire::burn_trees_system())
+    .add_system(harvest::harvest_system())
+    .add_system(life_form::clonal_spread_system())
+    .add_system(mortality::update_deadwood_system())
     .add_system(tree::update_offspring_system())
     .add_system(tree::drop_fruits_system())
+    .add_system(fruit::fruit_lifecycle_system())
+    .add_system(fruit_layer::update_fruit_layer_system())
+    .add_system(seed_bank::update_seed_bank_system())
+    .flush()
+    .add_system(genetics::update_genome_metrics_system())
+    .add_system(genetics::export_genome_metrics_system())
+    .add_system(disease::update_outbreak_metrics_system())
+    .add_system(carbon::update_carbon_system())
+    .add_system(inventory::report_inventory_system())
+    .add_system(events::log_events_system())
     .build()
     ;
 
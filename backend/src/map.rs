@@ -1,5 +1,9 @@
-use geo::{ Coordinate, Polygon, Rect };
-use super::movements::Position;
+use geo::{ Coordinate, LineString, Point, Polygon, Rect };
+use geo::algorithm::contains::Contains;
+use geo::algorithm::bounding_rect::BoundingRect;
+use super::movements::{ Position, Vector3 };
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum GroundType {
   Sand,
   Soil,
@@ -10,6 +14,24 @@ pub enum GroundType {
   Concreete,
 }
 
+impl GroundType {
+  // Part of water available to plant roots
+  pub fn moisture(&self) -> f32 {
+    match self {
+      GroundType::Soil => 0.7,
+      GroundType::Dirt => 0.5,
+      GroundType::Sand => 0.2,
+      GroundType::Rocks => 0.1,
+      GroundType::Water | GroundType::Asphalt | GroundType::Concreete => 0.0,
+    }
+  }
+
+  // Ground which carries fire, others stop it
+  pub fn flammable(&self) -> bool {
+    matches!(self, GroundType::Soil | GroundType::Dirt | GroundType::Sand)
+  }
+}
+
 
 struct GeographicFeature {
   tp: GroundType,
@@ -34,7 +56,48 @@ impl Map {
     Map(vec!(soil))
   }
 
+  // Features added later lie on top of earlier ones
+  pub fn add_feature(&mut self, tp: GroundType, area: Polygon<f32>) {
+    self.0.push(GeographicFeature { tp, area });
+  }
+
+  // Features from the bottom to the top
+  pub fn features(&self) -> impl Iterator<Item = (GroundType, &Polygon<f32>)> {
+    self.0.iter().map(|feature| (feature.tp, &feature.area))
+  }
+
+  pub fn ground_type(&self, position: &Position) -> Option<GroundType> {
+    let point = Point::new(position.0.x, position.0.y);
+    self.0.iter().rev().find(|feature| feature.area.contains(&point)).map(|feature| feature.tp)
+  }
+
+  // Lower left and upper right corners of the area covered by features
+  pub fn bounds(&self) -> (Vector3, Vector3) {
+    let mut rects = self.0.iter().filter_map(|feature| feature.area.bounding_rect());
+    if let Some(first) = rects.next() {
+      let (min, max) = rects.fold((first.min(), first.max()), |(min, max), rect| {
+        (
+          Coordinate { x: min.x.min(rect.min().x), y: min.y.min(rect.min().y) },
+          Coordinate { x: max.x.max(rect.max().x), y: max.y.max(rect.max().y) }
+        )
+      });
+      (Vector3::new(min.x, min.y, 0.0), Vector3::new(max.x, max.y, 0.0))
+    } else {
+      (Vector3::zeros(), Vector3::zeros())
+    }
+  }
+
+  pub fn contains(&self, position: &Position) -> bool {
+    let point = Point::new(position.0.x, position.0.y);
+    self.0.iter().any(|feature| feature.area.contains(&point))
+  }
+
   pub fn get_soil_fertility(&self, _position: &Position) -> f32 {
     1.0
   }
 }
+
+// Polygon from [x, y] points as they are written in config files
+pub fn polygon(points: &[[f32; 2]]) -> Polygon<f32> {
+  Polygon::new(LineString::from(points.iter().map(|p| (p[0], p[1])).collect::<Vec<_>>()), vec!())
+}
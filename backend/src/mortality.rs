@@ -0,0 +1,277 @@
+use legion::*;
+use legion::systems::CommandBuffer;
+use rand::Rng;
+use super::timer::Timers;
+use super::settings::Settings;
+use super::climate::Weather;
+use super::events::{ Events, Event };
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::random::SimRng;
+use super::tree::{ TreeType, TreeProperties, Offspring };
+use super::genetics::Genome;
+use super::disease::{ Infection, PathogenId };
+use super::drought::dies_of_drought;
+use super::succession::dies_of_shade;
+use super::carbon::{ CarbonAccounts, CarbonConfig, deadwood_left };
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeathCause {
+  Frost,
+  OldAge,
+  Starvation,
+  Background,
+  Rotten,
+  Eaten,
+  Senescence, // annual plant has seeded
+  Disease(PathogenId),
+  Fire,
+  Harvested,
+  Drought,
+  Shade, // suppressed by taller neighbours for too long
+}
+
+// Deadwood with less left is gone, the rest is negligible
+const MIN_DEADWOOD_LEFT: f32 = 0.01;
+
+// What is left from a dead tree. Entity keeps its Position and SpeciesId.
+pub struct Deadwood {
+  pub age: f32, // seconds since death
+}
+
+pub fn update_stress(
+  properties: &mut TreeProperties,
+  tree_type: &TreeType,
+  genome: &Genome,
+  tempreture: f32,
+  elapsed_seconds: f32
+) {
+  if tempreture < tree_type.frost_tempreture - genome.frost_tolerance {
+    properties.negative_temprature_constant_time += elapsed_seconds;
+  } else {
+    properties.negative_temprature_constant_time = 0.0;
+  }
+
+  if properties.resources < 0.0 {
+    properties.starvation_time += elapsed_seconds;
+  } else {
+    properties.starvation_time = 0.0;
+  }
+}
+
+// Probability to die during elapsed time for given yearly probability
+pub fn probability_for_period(yearly_probability: f32, elapsed_years: f32) -> f64 {
+  let yearly_probability = (yearly_probability as f64).clamp(0.0, 1.0);
+  (1.0 - (1.0 - yearly_probability).powf(elapsed_years as f64)).clamp(0.0, 1.0)
+}
+
+pub fn death_cause<R: Rng>(
+  properties: &TreeProperties,
+  tree_type: &TreeType,
+  settings: &Settings,
+  elapsed_seconds: f32,
+  rng: &mut R
+) -> Option<DeathCause> {
+  let years = properties.age / settings.seconds_in_year();
+  if properties.negative_temprature_constant_time > tree_type.negative_tempreture_survival * settings.day_duration {
+    Some(DeathCause::Frost)
+  } else if properties.starvation_time > tree_type.starvation_survival * settings.day_duration {
+    Some(DeathCause::Starvation)
+  } else if dies_of_drought(properties, tree_type) {
+    Some(DeathCause::Drought)
+  } else if dies_of_shade(properties, tree_type) {
+    Some(DeathCause::Shade)
+  } else if years > tree_type.max_age_years {
+    Some(DeathCause::OldAge)
+  } else if rng.gen_bool(probability_for_period(tree_type.background_mortality, elapsed_seconds / settings.seconds_in_year())) {
+    Some(DeathCause::Background)
+  } else {
+    None
+  }
+}
+
+// Several causes can meet in the same tick, the tree dies of the first one.
+// Returns false when the tree is already dead.
+pub fn kill_tree(
+  entity: &Entity,
+  properties: &mut TreeProperties,
+  cause: DeathCause,
+  command_buffer: &mut CommandBuffer,
+  settings: &Settings,
+  events: &mut Events
+) -> bool {
+  if properties.dead {
+    return false;
+  }
+  properties.dead = true;
+  events.emit(Event::EntityDied { entity: *entity, cause });
+  if settings.keep_deadwood {
+    command_buffer.remove_component::<TreeProperties>(*entity);
+    command_buffer.remove_component::<Offspring>(*entity);
+    command_buffer.remove_component::<Infection>(*entity);
+    command_buffer.add_component(*entity, Deadwood { age: 0.0 });
+  } else {
+    command_buffer.remove(*entity);
+  }
+  true
+}
+
+#[system(for_each)]
+#[allow(clippy::too_many_arguments)]
+pub fn mortality(
+  entity: &Entity,
+  species: &SpeciesId,
+  properties: &mut TreeProperties,
+  genome: &Genome,
+  command_buffer: &mut CommandBuffer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] weather: &Weather,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  if properties.dead {
+    return;
+  }
+  let tree_type = registry.get(*species);
+  update_stress(properties, tree_type, genome, weather.current_tempreture, time.long.elapsed_seconds);
+  if let Some(cause) = death_cause(properties, tree_type, settings, time.long.elapsed_seconds, rng) {
+    kill_tree(entity, properties, cause, command_buffer, settings, events);
+  }
+}
+
+// Deadwood decays at the rate of carbon accounting and is removed when
+// almost nothing is left
+pub fn decayed(deadwood: &Deadwood, config: &CarbonConfig, settings: &Settings) -> bool {
+  deadwood_left(config, deadwood.age / settings.seconds_in_year()) < MIN_DEADWOOD_LEFT
+}
+
+#[system(for_each)]
+pub fn update_deadwood(
+  entity: &Entity,
+  deadwood: &mut Deadwood,
+  command_buffer: &mut CommandBuffer,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] carbon: &CarbonAccounts,
+) {
+  deadwood.age += time.long.elapsed_seconds;
+  if decayed(deadwood, carbon.config(), settings) {
+    command_buffer.remove(*entity);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::map::Map;
+
+  fn oak() -> TreeType {
+    let registry = SpeciesRegistry::load();
+    registry.get(registry.find("oak").unwrap()).clone()
+  }
+
+  fn no_background(mut tree_type: TreeType) -> TreeType {
+    tree_type.background_mortality = 0.0;
+    tree_type
+  }
+
+  #[test]
+  fn young_healthy_tree_survives() {
+    let oak = no_background(oak());
+    let settings = Settings::default();
+    let tree = TreeProperties::new(settings.seconds_in_year(), 1.0);
+    let mut rng = SimRng::seeded(1);
+    assert_eq!(death_cause(&tree, &oak, &settings, 1.0, &mut rng), None);
+  }
+
+  #[test]
+  fn tree_dies_of_old_age() {
+    let oak = no_background(oak());
+    let settings = Settings::default();
+    let tree = TreeProperties::new((oak.max_age_years + 1.0) * settings.seconds_in_year(), 1.0);
+    let mut rng = SimRng::seeded(1);
+    assert_eq!(death_cause(&tree, &oak, &settings, 1.0, &mut rng), Some(DeathCause::OldAge));
+  }
+
+  #[test]
+  fn tree_dies_of_prolonged_frost() {
+    let oak = no_background(oak());
+    let settings = Settings::default();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    let mut rng = SimRng::seeded(1);
+    let frost = oak.frost_tempreture - 1.0;
+
+    update_stress(&mut tree, &oak, &Genome::default(), frost, settings.day_duration * oak.negative_tempreture_survival);
+    assert_eq!(death_cause(&tree, &oak, &settings, 1.0, &mut rng), None);
+
+    update_stress(&mut tree, &oak, &Genome::default(), frost, settings.day_duration);
+    assert_eq!(death_cause(&tree, &oak, &settings, 1.0, &mut rng), Some(DeathCause::Frost));
+
+    update_stress(&mut tree, &oak, &Genome::default(), oak.frost_tempreture + 1.0, 1.0);
+    assert_eq!(tree.negative_temprature_constant_time, 0.0);
+  }
+
+  #[test]
+  fn frost_tolerant_genome_resists_frost() {
+    let oak = oak();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    let hardy = Genome { frost_tolerance: 5.0, ..Genome::default() };
+    update_stress(&mut tree, &oak, &hardy, oak.frost_tempreture - 1.0, 1.0);
+    assert_eq!(tree.negative_temprature_constant_time, 0.0);
+    update_stress(&mut tree, &oak, &hardy, oak.frost_tempreture - 6.0, 1.0);
+    assert_eq!(tree.negative_temprature_constant_time, 1.0);
+  }
+
+  #[test]
+  fn tree_dies_of_starvation() {
+    let oak = no_background(oak());
+    let settings = Settings::default();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    let mut rng = SimRng::seeded(1);
+    tree.resources = -1.0;
+    update_stress(&mut tree, &oak, &Genome::default(), 20.0, settings.day_duration * (oak.starvation_survival + 1.0));
+    assert_eq!(death_cause(&tree, &oak, &settings, 1.0, &mut rng), Some(DeathCause::Starvation));
+  }
+
+  #[test]
+  fn tree_dies_of_long_drought() {
+    let oak = no_background(oak());
+    let settings = Settings::default();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    let mut rng = SimRng::seeded(1);
+    tree.drought_days = oak.drought.survival_days / 2.0;
+    assert_eq!(death_cause(&tree, &oak, &settings, 1.0, &mut rng), None);
+    tree.drought_days = oak.drought.survival_days + 1.0;
+    assert_eq!(death_cause(&tree, &oak, &settings, 1.0, &mut rng), Some(DeathCause::Drought));
+  }
+
+  #[test]
+  fn background_mortality_follows_yearly_probability() {
+    assert_eq!(probability_for_period(0.0, 1.0), 0.0);
+    assert!((probability_for_period(0.1, 1.0) - 0.1).abs() < 1e-6);
+    assert!((probability_for_period(0.1, 2.0) - 0.19).abs() < 1e-6);
+    assert_eq!(probability_for_period(2.0, 0.5), 1.0);
+    assert_eq!(probability_for_period(-1.0, 0.5), 0.0);
+  }
+
+  #[test]
+  fn rotten_deadwood_is_removed() {
+    let settings = Settings::default();
+    let mut world = World::default();
+    let fresh = world.push((Deadwood { age: 0.0 },));
+    let old = world.push((Deadwood { age: 1000.0 * settings.seconds_in_year() },));
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = settings.seconds_in_year();
+    let mut resources = Resources::default();
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(CarbonAccounts::covering(&Map::test_square(10.0, 10.0), 1.0, CarbonConfig::load()));
+    let mut schedule = Schedule::builder()
+      .add_system(update_deadwood_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+    assert!(world.entry(fresh).is_some());
+    assert!(world.entry(old).is_none());
+  }
+}
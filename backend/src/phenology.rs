@@ -0,0 +1,326 @@
+use serde::{ Serialize, Deserialize };
+use super::settings::Settings;
+use super::genetics::Genome;
+use super::tree::{ TreeProperties, TreeState, clamp, max };
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hemisphere {
+  Northern,
+  Southern,
+}
+
+impl Hemisphere {
+  // Part of the year local spring starts after the first of March
+  pub fn year_offset(&self) -> f32 {
+    match self {
+      Hemisphere::Northern => 0.0,
+      Hemisphere::Southern => 0.5,
+    }
+  }
+
+  // Part of the year since the start of local spring
+  pub fn local_year_time(&self, time_of_year: f32) -> f32 {
+    (time_of_year - self.year_offset()).rem_euclid(1.0)
+  }
+}
+
+// Named part of the year with behaviour of the given state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Phase {
+  pub name: String,
+  pub state: TreeState,
+  pub start: f32, // part of the year since the start of local spring
+}
+
+// Phases in the order they follow each other. Starts grow from phase to phase
+// except for one place where the list wraps across the year boundary.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Phases(Vec<Phase>);
+
+impl Phases {
+  pub fn validate(&self) -> Result<(), String> {
+    let phases = &self.0;
+    if phases.is_empty() {
+      return Err("no phases".into());
+    }
+    if let Some(phase) = phases.iter().find(|p| !(0.0..1.0).contains(&p.start)) {
+      return Err(format!("phase {} starts at {}, outside of the year", phase.name, phase.start));
+    }
+    let wraps = (0..phases.len())
+      .filter(|&i| phases[(i + 1) % phases.len()].start <= phases[i].start)
+      .count();
+    if phases.len() > 1 && wraps != 1 {
+      return Err("phases are not ordered through the year".into());
+    }
+    Ok(())
+  }
+
+  pub fn get(&self, ix: usize) -> &Phase {
+    &self.0[ix]
+  }
+
+  // Phase which started most recently before the given time of the year
+  pub fn select(&self, year_time: f32) -> usize {
+    let since_start = |phase: &Phase| (year_time - phase.start).rem_euclid(1.0);
+    (0..self.0.len())
+      .min_by(|&a, &b| since_start(&self.0[a]).partial_cmp(&since_start(&self.0[b])).unwrap_or(std::cmp::Ordering::Equal))
+      .unwrap_or(0)
+  }
+
+  // First phase with behaviour of the state
+  pub fn of_state(&self, state: &TreeState) -> Option<usize> {
+    self.0.iter().position(|phase| phase.state == *state)
+  }
+
+  // Part of the run of phases with the same state as the phase at year_time,
+  // which has already passed
+  pub fn progress(&self, year_time: f32) -> f32 {
+    let n = self.0.len();
+    let current = self.select(year_time);
+    let state = &self.0[current].state;
+    if self.0.iter().all(|phase| phase.state == *state) {
+      return year_time.rem_euclid(1.0);
+    }
+    let mut first = current;
+    while self.0[(first + n - 1) % n].state == *state {
+      first = (first + n - 1) % n;
+    }
+    let mut next = (current + 1) % n;
+    while self.0[next].state == *state {
+      next = (next + 1) % n;
+    }
+    let start = self.0[first].start;
+    let duration = (self.0[next].start - start).rem_euclid(1.0);
+    clamp((year_time - start).rem_euclid(1.0) / duration, 0.0, 1.0)
+  }
+}
+
+// Degree-days are counted in days of a real year, so thresholds do not
+// depend on how much the simulated year is compressed
+pub const DAYS_IN_YEAR: f32 = 365.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PhenologyModel {
+  // states switch at fixed parts of the year
+  Calendar,
+  // states switch by chilling and growing degree-days accumulated from weather
+  DegreeDays,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DegreeDayPhenology {
+  pub base_tempreture: f32, // growing degree-days are counted above it
+  pub chilling_tempreture: f32,
+  pub chilling_requirement: f32, // days of a real year below chilling_tempreture before buds can break
+  pub blossom_degree_days: f32,
+  pub ripening_degree_days: f32,
+  pub fall_degree_days: f32, // fruits are ripe and leaves fall even in warm weather
+  pub fall_tempreture: f32, // ripening tree starts to fall when it gets colder
+  pub fall_cooling: f32, // degree-days below fall_tempreture, so a cool spell does not end ripening
+  pub sleep_tempreture: f32, // falling tree goes to sleep when it gets colder
+}
+
+pub fn year_days(elapsed_seconds: f32, settings: &Settings) -> f32 {
+  elapsed_seconds / settings.seconds_in_year() * DAYS_IN_YEAR
+}
+
+// Chilling is gathered during dormancy, degree-days only after it is broken.
+// Cooling is gathered by ripening trees.
+pub fn accumulate_thermal_time(
+  properties: &mut TreeProperties,
+  phenology: &DegreeDayPhenology,
+  tempreture: f32,
+  elapsed_seconds: f32,
+  settings: &Settings
+) {
+  let days = year_days(elapsed_seconds, settings);
+  if tempreture < phenology.chilling_tempreture {
+    properties.chilling_days += days;
+  }
+  if properties.state != TreeState::Sleep || properties.chilling_days >= phenology.chilling_requirement {
+    properties.degree_days += max(0.0, tempreture - phenology.base_tempreture) * days;
+  }
+  if properties.state == TreeState::Ripening {
+    properties.cooling += max(0.0, phenology.fall_tempreture - tempreture) * days;
+  }
+}
+
+pub fn start_dormancy(properties: &mut TreeProperties) {
+  properties.chilling_days = 0.0;
+  properties.degree_days = 0.0;
+  properties.cooling = 0.0;
+}
+
+// Genome delays phenology by making every degree-day threshold larger
+fn threshold(degree_days: f32, genome: &Genome) -> f32 {
+  degree_days * (1.0 + genome.thermal_offset)
+}
+
+pub fn degree_day_state(
+  properties: &TreeProperties,
+  phenology: &DegreeDayPhenology,
+  genome: &Genome,
+  tempreture: f32
+) -> TreeState {
+  use TreeState::*;
+  let degree_days = properties.degree_days;
+  match properties.state {
+    Sleep if properties.chilling_days >= phenology.chilling_requirement
+      && degree_days >= threshold(phenology.blossom_degree_days, genome) => Blossom,
+    Blossom if degree_days >= threshold(phenology.ripening_degree_days, genome) => Ripening,
+    Ripening if degree_days >= threshold(phenology.fall_degree_days, genome)
+      || properties.cooling >= phenology.fall_cooling => Falling,
+    Falling if tempreture < phenology.sleep_tempreture => Sleep,
+    ref state => state.clone()
+  }
+}
+
+// Ripeness of fruits from 0.0 when ripening starts to 1.0 when they fall
+pub fn degree_day_maturity(properties: &TreeProperties, phenology: &DegreeDayPhenology, genome: &Genome) -> f32 {
+  let start = threshold(phenology.ripening_degree_days, genome);
+  let end = threshold(phenology.fall_degree_days, genome);
+  clamp((properties.degree_days - start) / (end - start), 0.0, 1.0)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::species::SpeciesRegistry;
+
+  fn phases(list: &[(&str, TreeState, f32)]) -> Phases {
+    Phases(list.iter().map(|(name, state, start)| Phase { name: name.to_string(), state: state.clone(), start: *start }).collect())
+  }
+
+  fn southern_like() -> Phases {
+    use TreeState::*;
+    phases(&[
+      ("flowering", Blossom, 0.8),
+      ("early_fruits", Ripening, 0.9),
+      ("late_fruits", Ripening, 0.1),
+      ("leaf_fall", Falling, 0.3),
+      ("dormancy", Sleep, 0.5),
+    ])
+  }
+
+  #[test]
+  fn phases_wrap_across_the_year() {
+    let phases = southern_like();
+    assert!(phases.validate().is_ok());
+    assert_eq!(phases.get(phases.select(0.85)).name, "flowering");
+    assert_eq!(phases.get(phases.select(0.95)).name, "early_fruits");
+    assert_eq!(phases.get(phases.select(0.0)).name, "early_fruits");
+    assert_eq!(phases.get(phases.select(0.2)).name, "late_fruits");
+    assert_eq!(phases.get(phases.select(0.6)).name, "dormancy");
+    assert_eq!(phases.get(phases.select(0.79)).name, "dormancy");
+    assert_eq!(phases.get(phases.select(1.85)).name, "flowering");
+    assert_eq!(phases.get(phases.select(-0.15)).name, "flowering");
+  }
+
+  #[test]
+  fn progress_spans_phases_with_the_same_state() {
+    let phases = southern_like();
+    assert!((phases.progress(0.9) - 0.0).abs() < 1e-6);
+    assert!((phases.progress(0.1) - 0.5).abs() < 1e-6);
+    assert!((phases.progress(0.25) - 0.875).abs() < 1e-6);
+  }
+
+  #[test]
+  fn disordered_phases_are_rejected() {
+    use TreeState::*;
+    let disordered = phases(&[("a", Sleep, 0.1), ("b", Blossom, 0.5), ("c", Ripening, 0.3), ("d", Falling, 0.7)]);
+    assert!(disordered.validate().is_err());
+    assert!(phases(&[("a", Sleep, 1.5)]).validate().is_err());
+    assert!(phases(&[]).validate().is_err());
+    assert!(phases(&[("a", Sleep, 0.1)]).validate().is_ok());
+  }
+
+  #[test]
+  fn southern_spring_starts_half_year_later() {
+    assert_eq!(Hemisphere::Northern.year_offset(), 0.0);
+    assert_eq!(Hemisphere::Southern.year_offset(), 0.5);
+    assert_eq!(Hemisphere::Southern.local_year_time(0.25), 0.75);
+  }
+
+  fn oak() -> DegreeDayPhenology {
+    let registry = SpeciesRegistry::load();
+    registry.get(registry.find("oak").unwrap()).phenology.clone()
+  }
+
+  // Runs the tree through given daily tempretures and returns its states
+  fn states(tempretures: &[f32]) -> Vec<TreeState> {
+    let phenology = oak();
+    let settings = Settings::default();
+    let day = settings.seconds_in_year() / DAYS_IN_YEAR;
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    tempretures.iter().map(|t| {
+      accumulate_thermal_time(&mut tree, &phenology, *t, day, &settings);
+      let state = degree_day_state(&tree, &phenology, &Genome::default(), *t);
+      if state == TreeState::Sleep && tree.state != TreeState::Sleep {
+        start_dormancy(&mut tree);
+      }
+      tree.state = state.clone();
+      state
+    }).collect()
+  }
+
+  #[test]
+  fn warm_winter_does_not_break_dormancy() {
+    let warm = vec!(20.0; 200);
+    assert!(states(&warm).iter().all(|s| *s == TreeState::Sleep));
+  }
+
+  #[test]
+  fn tree_goes_through_the_year() {
+    let phenology = oak();
+    let chilling = (phenology.chilling_requirement + 1.0) as usize;
+    let mut year = vec!(0.0; chilling);
+    year.extend(vec!(20.0; 200));
+    year.extend(vec!(0.0; 10));
+    let states = states(&year);
+    let first = |state| states.iter().position(|s| *s == state);
+    assert!(first(TreeState::Blossom).unwrap() > chilling);
+    assert!(first(TreeState::Ripening) > first(TreeState::Blossom));
+    assert!(first(TreeState::Falling) > first(TreeState::Ripening));
+    assert_eq!(states.last(), Some(&TreeState::Sleep));
+  }
+
+  #[test]
+  fn cold_spring_delays_bud_burst() {
+    let phenology = oak();
+    let chilling = (phenology.chilling_requirement + 1.0) as usize;
+    let blossom_day = |spring: f32| {
+      let mut year = vec!(0.0; chilling);
+      year.extend(vec!(spring; 200));
+      states(&year).iter().position(|s| *s == TreeState::Blossom).unwrap()
+    };
+    assert!(blossom_day(10.0) > blossom_day(15.0));
+  }
+
+  #[test]
+  fn cool_spell_does_not_end_ripening() {
+    let phenology = oak();
+    let chilling = (phenology.chilling_requirement + 1.0) as usize;
+    let mut year = vec!(0.0; chilling);
+    year.extend(vec!(20.0; 30));
+    let ripening = states(&year).iter().position(|s| *s == TreeState::Ripening).unwrap();
+    year.truncate(ripening + 1);
+    year.push(phenology.fall_tempreture - 2.0);
+    year.extend(vec!(20.0; 5));
+    assert!(states(&year)[ripening..].iter().all(|s| *s == TreeState::Ripening));
+
+    year.extend(vec!(phenology.fall_tempreture - 2.0; 30));
+    assert_eq!(states(&year).last(), Some(&TreeState::Falling));
+  }
+
+  #[test]
+  fn maturity_follows_degree_days() {
+    let phenology = oak();
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    tree.degree_days = phenology.ripening_degree_days;
+    assert_eq!(degree_day_maturity(&tree, &phenology, &Genome::default()), 0.0);
+    tree.degree_days = (phenology.ripening_degree_days + phenology.fall_degree_days) / 2.0;
+    assert!((degree_day_maturity(&tree, &phenology, &Genome::default()) - 0.5).abs() < 1e-6);
+  }
+}
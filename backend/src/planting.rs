@@ -0,0 +1,306 @@
+use legion::*;
+use rand::Rng;
+use serde::{ Serialize, Deserialize };
+use geo::{ Point, Polygon };
+use geo::algorithm::contains::Contains;
+use geo::algorithm::bounding_rect::BoundingRect;
+use geo::algorithm::euclidean_distance::EuclideanDistance;
+use super::settings::Settings;
+use super::map::{ Map, polygon };
+use super::movements::{ Position, Vector3 };
+use super::spatial::{ SpatialIndex, distance_2d };
+use super::species::SpeciesRegistry;
+use super::tree::{ TreeProperties, find_species, place_tree_test };
+
+// Attempts per requested tree before random planting gives up
+const RANDOM_ATTEMPTS: usize = 100;
+
+// How planted trees are laid out, distances are in meters
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "pattern", rename_all = "snake_case")]
+pub enum Pattern {
+  // square grid
+  Grid {
+    spacing: f32
+  },
+  // rows turned by direction degrees counterclockwise from east
+  Rows {
+    row_spacing: f32,
+    spacing: f32, // between trees in a row
+    #[serde(default)]
+    direction: f32
+  },
+  // scattered trees keeping min_distance from each other
+  Random {
+    count: usize,
+    min_distance: f32
+  },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlantingPlan {
+  pub species: String,
+  pub age_years: f32,
+  pub size: f32,
+  #[serde(flatten)]
+  pub pattern: Pattern,
+  pub area: Vec<[f32; 2]>,
+}
+
+impl Pattern {
+  pub fn validate(&self) -> Result<(), String> {
+    let valid = match self {
+      Pattern::Grid { spacing } => *spacing > 0.0,
+      Pattern::Rows { row_spacing, spacing, .. } => *row_spacing > 0.0 && *spacing > 0.0,
+      Pattern::Random { min_distance, .. } => *min_distance >= 0.0,
+    };
+    if valid {
+      Ok(())
+    } else {
+      Err("spacing should be positive".into())
+    }
+  }
+
+  // Closest distance between trees of the pattern
+  pub fn spacing(&self) -> f32 {
+    match self {
+      Pattern::Grid { spacing } => *spacing,
+      Pattern::Rows { row_spacing, spacing, .. } => spacing.min(*row_spacing),
+      Pattern::Random { min_distance, .. } => *min_distance,
+    }
+  }
+}
+
+// Trees can be planted only on ground where plants grow, keeping margin
+// from features where they could not
+fn plantable(map: &Map, position: &Position, margin: f32) -> bool {
+  let point = Point::new(position.0.x, position.0.y);
+  map.ground_type(position).is_some_and(|ground| ground.moisture() > 0.0)
+    && map.features().all(|(ground, area)| ground.moisture() > 0.0 || point.euclidean_distance(area) >= margin)
+}
+
+fn contains(area: &Polygon<f32>, position: &Position) -> bool {
+  area.contains(&Point::new(position.0.x, position.0.y))
+}
+
+// Regular lattice anchored at the lower left corner of the area
+fn lattice(area: &Polygon<f32>, spacing: f32, row_spacing: f32, direction: f32) -> Vec<Position> {
+  let rect = match area.bounding_rect() {
+    Some(rect) => rect,
+    None => return Vec::new()
+  };
+  let origin = Vector3::new(rect.min().x, rect.min().y, 0.0);
+  let along = Vector3::new(direction.cos(), direction.sin(), 0.0);
+  let across = Vector3::new(-direction.sin(), direction.cos(), 0.0);
+  let diagonal = rect.width().hypot(rect.height());
+  let steps = |distance: f32| (diagonal / distance).ceil() as i32;
+  let (n, m) = (steps(spacing), steps(row_spacing));
+  let mut positions = Vec::new();
+  for j in -m..=m {
+    for i in -n..=n {
+      let offset = along * (i as f32 + 0.5) * spacing + across * (j as f32 + 0.5) * row_spacing;
+      let position = Position(origin + offset);
+      if contains(area, &position) {
+        positions.push(position);
+      }
+    }
+  }
+  positions
+}
+
+fn scatter<R: Rng>(area: &Polygon<f32>, count: usize, min_distance: f32, rng: &mut R) -> Vec<Position> {
+  let rect = match area.bounding_rect() {
+    Some(rect) if rect.width() > 0.0 && rect.height() > 0.0 => rect,
+    _ => return Vec::new()
+  };
+  let mut positions: Vec<Position> = Vec::new();
+  for _ in 0..count * RANDOM_ATTEMPTS {
+    if positions.len() == count {
+      break;
+    }
+    let x = rng.gen_range(rect.min().x..rect.max().x);
+    let y = rng.gen_range(rect.min().y..rect.max().y);
+    let position = Position(Vector3::new(x, y, 0.0));
+    if contains(area, &position) && positions.iter().all(|p| distance_2d(&p.0, &position.0) >= min_distance) {
+      positions.push(position);
+    }
+  }
+  positions
+}
+
+// Positions of trees of the pattern inside the area, leaving out ground
+// where they could not grow and half of the spacing around it
+pub fn layout<R: Rng>(pattern: &Pattern, area: &Polygon<f32>, map: &Map, rng: &mut R) -> Result<Vec<Position>, String> {
+  pattern.validate()?;
+  let positions = match pattern {
+    Pattern::Grid { spacing } => lattice(area, *spacing, *spacing, 0.0),
+    Pattern::Rows { row_spacing, spacing, direction } => lattice(area, *spacing, *row_spacing, direction.to_radians()),
+    Pattern::Random { count, min_distance } => scatter(area, *count, *min_distance, rng),
+  };
+  let margin = pattern.spacing() / 2.0;
+  Ok(positions.into_iter().filter(|position| plantable(map, position, margin)).collect())
+}
+
+// Places trees of the plan into the world, leaving out positions whose
+// nearest tree already growing there is not farther than the spacing
+pub fn plant<R: Rng>(
+  world: &mut World,
+  registry: &SpeciesRegistry,
+  plan: &PlantingPlan,
+  map: &Map,
+  settings: &Settings,
+  rng: &mut R
+) -> Result<Vec<Entity>, String> {
+  find_species(registry, &plan.species)?;
+  let spacing = plan.pattern.spacing();
+  let positions = layout(&plan.pattern, &polygon(&plan.area), map, rng)?;
+  let mut trees = SpatialIndex::<TreeProperties>::new(spacing.max(1.0));
+  let mut query = <(Entity, &Position, &TreeProperties)>::query();
+  for (entity, position, _) in query.iter(world) {
+    trees.insert(*entity, position);
+  }
+  let age = plan.age_years * settings.seconds_in_year();
+  let free: Vec<Position> = positions.into_iter()
+    .filter(|position| trees.nearest(position, 1).into_iter()
+      .filter_map(|tree| world.entry_ref(tree).ok().and_then(|entry| entry.get_component::<Position>().ok().copied()))
+      .all(|tree| distance_2d(&tree.0, &position.0) > spacing))
+    .collect();
+  free.into_iter()
+    .map(|position| place_tree_test(world, registry, &plan.species, position, age, plan.size))
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use geo::{ Coordinate, Rect };
+  use super::super::map::GroundType;
+  use super::super::random::SimRng;
+  use super::super::species::SpeciesId;
+  use super::super::allometry::Dimensions;
+
+  fn square(size: f32) -> Polygon<f32> {
+    polygon(&[[0.0, 0.0], [size, 0.0], [size, size], [0.0, size]])
+  }
+
+  #[test]
+  fn grid_fills_the_area() {
+    let map = Map::test_square(100.0, 100.0);
+    let mut rng = SimRng::seeded(1);
+    let positions = layout(&Pattern::Grid { spacing: 2.0 }, &square(10.0), &map, &mut rng).unwrap();
+    assert_eq!(positions.len(), 25);
+    assert!(positions.contains(&Position(Vector3::new(1.0, 1.0, 0.0))));
+    assert!(positions.contains(&Position(Vector3::new(9.0, 9.0, 0.0))));
+  }
+
+  #[test]
+  fn rows_follow_direction() {
+    let map = Map::test_square(100.0, 100.0);
+    let mut rng = SimRng::seeded(1);
+    let rows = Pattern::Rows { row_spacing: 5.0, spacing: 1.0, direction: 90.0 };
+    let positions = layout(&rows, &square(10.0), &map, &mut rng).unwrap();
+    assert_eq!(positions.len(), 2 * 10);
+    let mut columns: Vec<i32> = positions.iter().map(|p| (p.0.x / 5.0).floor() as i32).collect();
+    columns.dedup();
+    assert_eq!(columns.len(), 2);
+  }
+
+  #[test]
+  fn random_planting_keeps_distance() {
+    let map = Map::test_square(100.0, 100.0);
+    let mut rng = SimRng::seeded(1);
+    let pattern = Pattern::Random { count: 20, min_distance: 1.5 };
+    let positions = layout(&pattern, &square(10.0), &map, &mut rng).unwrap();
+    assert_eq!(positions.len(), 20);
+    for (i, a) in positions.iter().enumerate() {
+      for b in &positions[i + 1..] {
+        assert!(distance_2d(&a.0, &b.0) >= 1.5);
+      }
+    }
+  }
+
+  #[test]
+  fn trees_are_not_planted_into_water() {
+    let mut map = Map::test_square(100.0, 100.0);
+    map.add_feature(GroundType::Water, Polygon::from(Rect::new(Coordinate { x: 0.0, y: 0.0 }, Coordinate { x: 4.0, y: 10.0 })));
+    let mut rng = SimRng::seeded(1);
+    let positions = layout(&Pattern::Grid { spacing: 2.0 }, &square(10.0), &map, &mut rng).unwrap();
+    assert_eq!(positions.len(), 15);
+    assert!(positions.iter().all(|p| p.0.x > 4.0));
+    let outside = layout(&Pattern::Grid { spacing: 2.0 }, &polygon(&[[100.0, 100.0], [110.0, 100.0], [110.0, 110.0]]), &map, &mut rng).unwrap();
+    assert!(outside.is_empty());
+    let (_, water) = map.features().last().unwrap();
+    assert!(layout(&Pattern::Grid { spacing: 2.0 }, water, &map, &mut rng).unwrap().is_empty());
+  }
+
+  #[test]
+  fn trees_keep_margin_from_road() {
+    let mut map = Map::test_square(100.0, 100.0);
+    map.add_feature(GroundType::Asphalt, Polygon::from(Rect::new(Coordinate { x: 0.0, y: 0.0 }, Coordinate { x: 5.0, y: 12.0 })));
+    let mut rng = SimRng::seeded(1);
+    let positions = layout(&Pattern::Grid { spacing: 4.0 }, &square(12.0), &map, &mut rng).unwrap();
+    // the column at x = 2 is on the road and the one at x = 6 only a meter from it
+    assert_eq!(positions.len(), 3);
+    assert!(positions.iter().all(|p| p.0.x == 10.0));
+  }
+
+  #[test]
+  fn existing_trees_keep_their_space() {
+    let map = Map::test_square(100.0, 100.0);
+    let registry = SpeciesRegistry::load();
+    let mut rng = SimRng::seeded(1);
+    let mut world = World::default();
+    place_tree_test(&mut world, &registry, "oak", Position(Vector3::new(2.0, 2.0, 0.0)), 0.0, 10.0).unwrap();
+    let plan: PlantingPlan = serde_yaml::from_str("
+      { species: hazel, age_years: 1, size: 1, pattern: grid, spacing: 2, area: [[0, 0], [4, 0], [4, 4], [0, 4]] }
+    ").unwrap();
+    let planted = plant(&mut world, &registry, &plan, &map, &Settings::default(), &mut rng).unwrap();
+    // all four grid places are closer to the oak than the spacing
+    assert!(planted.is_empty());
+    let plan = PlantingPlan { area: vec!([0.0, 0.0], [8.0, 0.0], [8.0, 2.0], [0.0, 2.0]), ..plan };
+    let planted = plant(&mut world, &registry, &plan, &map, &Settings::default(), &mut rng).unwrap();
+    assert_eq!(planted.len(), 2);
+    // planting the same plan again finds no free place
+    assert!(plant(&mut world, &registry, &plan, &map, &Settings::default(), &mut rng).unwrap().is_empty());
+  }
+
+  #[test]
+  fn invalid_plans_are_rejected() {
+    let map = Map::test_square(100.0, 100.0);
+    let mut rng = SimRng::seeded(1);
+    assert!(layout(&Pattern::Grid { spacing: 0.0 }, &square(10.0), &map, &mut rng).is_err());
+    let plan: PlantingPlan = serde_yaml::from_str("
+      { species: palm, age_years: 1, size: 1, pattern: grid, spacing: 2, area: [[0, 0], [4, 0], [4, 4]] }
+    ").unwrap();
+    let mut world = World::default();
+    let registry = SpeciesRegistry::load();
+    assert!(plant(&mut world, &registry, &plan, &map, &Settings::default(), &mut rng).is_err());
+  }
+
+  #[test]
+  fn orchard_is_planted_with_given_age() {
+    let map = Map::test_square(100.0, 100.0);
+    let registry = SpeciesRegistry::load();
+    let settings = Settings::default();
+    let mut rng = SimRng::seeded(1);
+    let mut world = World::default();
+    let plan: PlantingPlan = serde_yaml::from_str("
+      species: hazel
+      age_years: 3
+      size: 1
+      pattern: rows
+      row_spacing: 4
+      spacing: 2
+      area: [[0, 0], [8, 0], [8, 8], [0, 8]]
+    ").unwrap();
+    let planted = plant(&mut world, &registry, &plan, &map, &settings, &mut rng).unwrap();
+    assert_eq!(planted.len(), 8);
+    let hazel = registry.find("hazel").unwrap();
+    let mut query = <(&SpeciesId, &TreeProperties, &Dimensions)>::query();
+    for (species, properties, dimensions) in query.iter(&world) {
+      assert_eq!(*species, hazel);
+      assert_eq!(properties.age, 3.0 * settings.seconds_in_year());
+      assert!(dimensions.height > 0.0);
+    }
+  }
+}
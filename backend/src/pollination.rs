@@ -0,0 +1,224 @@
+use legion::*;
+use legion::world::SubWorld;
+use serde::{ Serialize, Deserialize };
+use super::timer::Timers;
+use super::climate::Weather;
+use super::genetics::Genome;
+use super::movements::Position;
+use super::spatial::SpatialIndex;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeProperties, TreeState, clamp, min };
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "by", rename_all = "snake_case")]
+pub enum Pollination {
+  // flowering neighbours are looked for within genetics pollination_radius
+  Wind {
+    partners: usize,
+  },
+  Insects {
+    min_tempreture: f32,
+    optimal_tempreture: f32,
+  },
+}
+
+// Part of flowers pollinated when the given amount of conspecifics flowers
+// nearby
+pub fn wind_pollination(flowering_neighbours: usize, partners: usize) -> f32 {
+  if partners == 0 {
+    1.0
+  } else {
+    min(1.0, flowering_neighbours as f32 / partners as f32)
+  }
+}
+
+// Activity of pollinators at the tempreture, from 0.0 to 1.0
+pub fn insect_activity(tempreture: f32, min_tempreture: f32, optimal_tempreture: f32) -> f32 {
+  if optimal_tempreture <= min_tempreture {
+    return if tempreture >= min_tempreture { 1.0 } else { 0.0 };
+  }
+  clamp((tempreture - min_tempreture) / (optimal_tempreture - min_tempreture), 0.0, 1.0)
+}
+
+// Fruit set is the average pollination success over the blossom
+pub fn add_pollination(properties: &mut TreeProperties, success: f32, elapsed_seconds: f32) {
+  let time = properties.blossom_time + elapsed_seconds;
+  if time > 0.0 {
+    properties.fruit_set = (properties.fruit_set * properties.blossom_time + success * elapsed_seconds) / time;
+  }
+  properties.blossom_time = time;
+}
+
+// Trees not pollinated during the next blossom set fruit fully
+pub fn reset_pollination(properties: &mut TreeProperties) {
+  properties.fruit_set = 1.0;
+  properties.blossom_time = 0.0;
+  properties.pollen.clear();
+}
+
+// Flowering conspecifics within pollination radius set fruit of blossoming
+// trees and pass their genomes on to it
+#[system]
+#[read_component(Position)]
+#[read_component(SpeciesId)]
+#[read_component(Genome)]
+#[write_component(TreeProperties)]
+pub fn pollinate(
+  world: &mut SubWorld,
+  #[resource] time: &Timers,
+  #[resource] weather: &Weather,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] index: &SpatialIndex<TreeProperties>,
+) {
+  let mut query = <(Entity, &Position, &SpeciesId, &TreeProperties)>::query();
+  let blossoming: Vec<(Entity, Position, SpeciesId)> = query.iter(world)
+    .filter(|(_, _, _, properties)| properties.state == TreeState::Blossom)
+    .map(|(entity, position, species, _)| (*entity, *position, *species))
+    .collect();
+
+  let mut pollinated = Vec::new();
+  for (entity, position, species) in blossoming {
+    let tree_type = registry.get(species);
+    let pollen: Vec<Genome> = index.within_radius(&position, tree_type.genetics.pollination_radius)
+      .filter(|other| *other != entity)
+      .filter_map(|other| world.entry_ref(other).ok())
+      .filter(|other| other.get_component::<SpeciesId>().is_ok_and(|s| *s == species))
+      .filter(|other| other.get_component::<TreeProperties>().is_ok_and(|p| p.state == TreeState::Blossom))
+      .filter_map(|other| other.get_component::<Genome>().ok().cloned())
+      .collect();
+    let success = match tree_type.pollination {
+      Pollination::Wind { partners } => wind_pollination(pollen.len(), partners),
+      Pollination::Insects { min_tempreture, optimal_tempreture } =>
+        insect_activity(weather.current_tempreture, min_tempreture, optimal_tempreture),
+    };
+    pollinated.push((entity, success, pollen));
+  }
+
+  for (entity, success, pollen) in pollinated {
+    if let Ok(mut entry) = world.entry_mut(entity) {
+      if let Ok(properties) = entry.get_component_mut::<TreeProperties>() {
+        add_pollination(properties, success, time.long.elapsed_seconds);
+        // Neighbours flowering later during the blossom are fathers too
+        for genome in pollen {
+          if !properties.pollen.contains(&genome) {
+            properties.pollen.push(genome);
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::movements::Vector3;
+  use super::super::tree::place_tree_test;
+
+  #[test]
+  fn wind_pollination_needs_partners() {
+    assert_eq!(wind_pollination(0, 3), 0.0);
+    assert!((wind_pollination(1, 3) - 1.0 / 3.0).abs() < 1e-6);
+    assert_eq!(wind_pollination(5, 3), 1.0);
+  }
+
+  #[test]
+  fn insects_fly_in_warm_weather() {
+    assert_eq!(insect_activity(5.0, 8.0, 20.0), 0.0);
+    assert_eq!(insect_activity(14.0, 8.0, 20.0), 0.5);
+    assert_eq!(insect_activity(25.0, 8.0, 20.0), 1.0);
+  }
+
+  #[test]
+  fn fruit_set_averages_over_blossom() {
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    add_pollination(&mut tree, 0.0, 1.0);
+    assert_eq!(tree.fruit_set, 0.0);
+    add_pollination(&mut tree, 1.0, 3.0);
+    assert_eq!(tree.fruit_set, 0.75);
+    reset_pollination(&mut tree);
+    assert_eq!(tree.fruit_set, 1.0);
+  }
+
+  #[test]
+  fn isolated_trees_are_not_pollinated() {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    let mut index = SpatialIndex::<TreeProperties>::new(10.0);
+    let mut plant = |name, x: f32| {
+      let position = Position(Vector3::new(x, 0.0, 0.0));
+      let entity = place_tree_test(&mut world, &registry, name, position, 0.0, 5.0).unwrap();
+      world.entry(entity).unwrap().get_component_mut::<TreeProperties>().unwrap().state = TreeState::Blossom;
+      index.insert(entity, &position);
+      entity
+    };
+    let grove: Vec<Entity> = (0..4).map(|i| plant("oak", i as f32 * 5.0)).collect();
+    let isolated = plant("oak", 500.0);
+    let hazel = plant("hazel", 510.0);
+    let poppy = plant("poppy", 520.0);
+    for (i, tree) in grove.iter().enumerate() {
+      *world.entry(*tree).unwrap().get_component_mut::<Genome>().unwrap() = Genome { fertility: 1.0 + i as f32, ..Genome::default() };
+    }
+
+    let mut timers = Timers::default();
+    timers.long.elapsed_seconds = 1.0;
+    let mut weather = Weather::prepare("moderate");
+    weather.current_tempreture = 14.0;
+    let mut resources = Resources::default();
+    resources.insert(timers);
+    resources.insert(weather);
+    resources.insert(registry);
+    resources.insert(index);
+    let mut schedule = Schedule::builder()
+      .add_system(pollinate_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let fruit_set = |entity| world.entry_ref(entity).unwrap().get_component::<TreeProperties>().unwrap().fruit_set;
+    assert!(grove.iter().all(|tree| fruit_set(*tree) == 1.0));
+    assert_eq!(fruit_set(isolated), 0.0);
+    assert_eq!(fruit_set(hazel), 0.0);
+    assert!((fruit_set(poppy) - 0.5).abs() < 1e-6);
+    let pollen = |entity| world.entry_ref(entity).unwrap().get_component::<TreeProperties>().unwrap().pollen.len();
+    assert!(grove.iter().all(|tree| pollen(*tree) == 3));
+    assert_eq!(pollen(isolated), 0);
+  }
+
+  #[test]
+  fn blossoming_neighbour_passes_its_genome() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    let radius = registry.get(oak).genetics.pollination_radius;
+    let mut world = World::default();
+    let mut index = SpatialIndex::<TreeProperties>::new(5.0);
+    let mut plant = |x: f32, state: TreeState, genome: Genome| {
+      let position = Position(Vector3::new(x, 0.0, 0.0));
+      let properties = TreeProperties { state, ..TreeProperties::new(0.0, 5.0) };
+      let entity = world.push((position, oak, properties, genome));
+      index.insert(entity, &position);
+      entity
+    };
+    let father = Genome { fertility: 2.0, ..Genome::default() };
+    let mother = plant(0.0, TreeState::Blossom, Genome::default());
+    plant(1.0, TreeState::Blossom, father.clone());
+    let late_father = Genome { fertility: 3.0, ..Genome::default() };
+    let late = plant(2.0, TreeState::Sleep, late_father.clone());
+    plant(radius + 1.0, TreeState::Blossom, Genome { fertility: 4.0, ..Genome::default() });
+
+    let mut resources = Resources::default();
+    resources.insert(Timers::default());
+    resources.insert(Weather::prepare("moderate"));
+    resources.insert(registry);
+    resources.insert(index);
+    let mut schedule = Schedule::builder()
+      .add_system(pollinate_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+    let pollen = |world: &World| world.entry_ref(mother).unwrap().get_component::<TreeProperties>().unwrap().pollen.clone();
+    assert_eq!(pollen(&world), vec!(father.clone()));
+
+    world.entry(late).unwrap().get_component_mut::<TreeProperties>().unwrap().state = TreeState::Blossom;
+    schedule.execute(&mut world, &mut resources);
+    assert_eq!(pollen(&world), vec!(father, late_father));
+  }
+}
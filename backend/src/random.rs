@@ -0,0 +1,43 @@
+use rand::{ Rng, RngCore, SeedableRng };
+use rand::rngs::StdRng;
+
+// Random generator shared by simulation systems, so a run can be reproduced
+// by seeding it.
+pub struct SimRng(StdRng);
+
+impl SimRng {
+  pub fn seeded(seed: u64) -> Self {
+    SimRng(StdRng::seed_from_u64(seed))
+  }
+}
+
+// Whole amount of events with the expected mean, the fraction is rounded at
+// random
+pub fn random_count<R: Rng>(expected: f32, rng: &mut R) -> u32 {
+  let whole = expected.floor();
+  whole as u32 + if rng.gen::<f32>() < expected - whole { 1 } else { 0 }
+}
+
+impl Default for SimRng {
+  fn default() -> Self {
+    SimRng(StdRng::from_entropy())
+  }
+}
+
+impl RngCore for SimRng {
+  fn next_u32(&mut self) -> u32 {
+    self.0.next_u32()
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    self.0.next_u64()
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    self.0.fill_bytes(dest)
+  }
+
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+    self.0.try_fill_bytes(dest)
+  }
+}
@@ -0,0 +1,115 @@
+use super::map::Map;
+use super::movements::{ Position, Vector3 };
+
+// Regular grid of cells covering the map, used for per-cell layers
+#[derive(Clone)]
+pub struct Raster<T> {
+  origin: Vector3,
+  cell_size: f32,
+  width: usize,
+  height: usize,
+  cells: Vec<T>,
+}
+
+impl<T: Clone> Raster<T> {
+  pub fn new(origin: Vector3, cell_size: f32, width: usize, height: usize, value: T) -> Self {
+    Raster {
+      origin,
+      cell_size,
+      width,
+      height,
+      cells: vec!(value; width * height)
+    }
+  }
+
+  pub fn covering(map: &Map, cell_size: f32, value: T) -> Self {
+    let (min, max) = map.bounds();
+    let width = ((max.x - min.x) / cell_size).ceil().max(1.0) as usize;
+    let height = ((max.y - min.y) / cell_size).ceil().max(1.0) as usize;
+    Raster::new(min, cell_size, width, height, value)
+  }
+}
+
+impl<T> Raster<T> {
+  pub fn cell_size(&self) -> f32 {
+    self.cell_size
+  }
+
+  pub fn len(&self) -> usize {
+    self.cells.len()
+  }
+
+  pub fn cell_index(&self, position: &Position) -> Option<usize> {
+    let x = ((position.0.x - self.origin.x) / self.cell_size).floor();
+    let y = ((position.0.y - self.origin.y) / self.cell_size).floor();
+    if x < 0.0 || y < 0.0 || x as usize >= self.width || y as usize >= self.height {
+      None
+    } else {
+      Some(y as usize * self.width + x as usize)
+    }
+  }
+
+  pub fn cell_center(&self, ix: usize) -> Position {
+    let x = (ix % self.width) as f32 + 0.5;
+    let y = (ix / self.width) as f32 + 0.5;
+    Position(self.origin + Vector3::new(x * self.cell_size, y * self.cell_size, 0.0))
+  }
+
+  // Indices of cells sharing a side with the given one
+  pub fn neighbours(&self, ix: usize) -> impl Iterator<Item = usize> {
+    let (x, y, width, height) = (ix % self.width, ix / self.width, self.width, self.height);
+    let left = if x > 0 { Some(ix - 1) } else { None };
+    let right = if x + 1 < width { Some(ix + 1) } else { None };
+    let down = if y > 0 { Some(ix - width) } else { None };
+    let up = if y + 1 < height { Some(ix + width) } else { None };
+    left.into_iter().chain(right).chain(down).chain(up)
+  }
+
+  pub fn get(&self, position: &Position) -> Option<&T> {
+    self.cell_index(position).map(move |ix| &self.cells[ix])
+  }
+
+  pub fn get_mut(&mut self, position: &Position) -> Option<&mut T> {
+    self.cell_index(position).map(move |ix| &mut self.cells[ix])
+  }
+
+  pub fn cell(&self, ix: usize) -> &T {
+    &self.cells[ix]
+  }
+
+  pub fn cell_mut(&mut self, ix: usize) -> &mut T {
+    &mut self.cells[ix]
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &T> {
+    self.cells.iter()
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+    self.cells.iter_mut()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn raster_covers_the_map() {
+    let map = Map::test_square(10.0, 20.0);
+    let raster = Raster::covering(&map, 2.0, 0);
+    assert_eq!(raster.len(), 5 * 10);
+    assert_eq!(raster.cell_index(&Position(Vector3::new(-5.0, -10.0, 0.0))), Some(0));
+    assert_eq!(raster.cell_index(&Position(Vector3::new(4.9, 9.9, 0.0))), Some(49));
+    assert_eq!(raster.cell_index(&Position(Vector3::new(5.1, 0.0, 0.0))), None);
+    assert_eq!(raster.cell_center(0), Position(Vector3::new(-4.0, -9.0, 0.0)));
+  }
+
+  #[test]
+  fn neighbours_stay_inside() {
+    let map = Map::test_square(10.0, 20.0);
+    let raster = Raster::covering(&map, 2.0, 0);
+    assert_eq!(raster.neighbours(0).collect::<Vec<_>>(), vec!(1, 5));
+    assert_eq!(raster.neighbours(6).count(), 4);
+  }
+}
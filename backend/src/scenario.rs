@@ -0,0 +1,26 @@
+use serde::{ Serialize, Deserialize };
+use super::harvest::ManagementPlan;
+use super::planting::PlantingPlan;
+use super::inventory::InventoryConfig;
+use super::fire::PlannedIgnition;
+
+static SCENARIO: &str = include_str!("../config/scenario.yaml");
+
+// Interventions planned for the simulated stand
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Scenario {
+  #[serde(default)]
+  pub planting: Vec<PlantingPlan>, // planted at the start
+  #[serde(default)]
+  pub management: Vec<ManagementPlan>,
+  #[serde(default)]
+  pub ignitions: Vec<PlannedIgnition>,
+  #[serde(default)]
+  pub inventory: Option<InventoryConfig>, // taken from the start at the interval
+}
+
+impl Scenario {
+  pub fn load() -> Self {
+    serde_yaml::from_str(SCENARIO).unwrap()
+  }
+}
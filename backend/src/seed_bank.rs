@@ -0,0 +1,260 @@
+use legion::*;
+use legion::systems::CommandBuffer;
+use rand::Rng;
+use super::timer::{ Timers, Season };
+use super::settings::Settings;
+use super::climate::Weather;
+use super::map::Map;
+use super::movements::{ Position, Vector3 };
+use super::events::{ Events, Event };
+use super::random::{ SimRng, random_count };
+use super::raster::Raster;
+use super::spatial::SpatialIndex;
+use super::fruit::site_suitability;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, place_tree, min };
+use super::genetics::Parents;
+use super::grass::GrassLayer;
+use super::succession::can_germinate;
+use super::phenology::year_days;
+use serde::{ Serialize, Deserialize };
+
+// Cohorts with less seeds than this are dropped from the bank
+const MIN_SEEDS: f32 = 0.01;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeedCohort {
+  pub species: SpeciesId,
+  pub amount: f32,
+  pub age: f32, // seconds
+  pub chilling: f32, // days of a real year spent below stratification tempreture
+  pub parents: Parents,
+  pub mother: Option<Entity>, // tree which has produced the seeds
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SeedDormancy {
+  pub dormancy_days: f32,
+  pub stratification_tempreture: f32,
+  pub stratification_days: f32,
+  pub half_life_years: f32,
+  pub germination_rate: f32,
+}
+
+// Seeds lying in the soil, grouped into cohorts per map cell
+pub struct SeedBank(Raster<Vec<SeedCohort>>);
+
+impl SeedBank {
+  pub fn covering(map: &Map, cell_size: f32) -> Self {
+    SeedBank(Raster::covering(map, cell_size, Vec::new()))
+  }
+
+  // Returns false when position is outside of the bank
+  pub fn deposit(
+    &mut self,
+    position: &Position,
+    species: SpeciesId,
+    amount: f32,
+    parents: &Parents,
+    mother: Option<Entity>
+  ) -> bool {
+    if let Some(cohorts) = self.0.get_mut(position) {
+      let fresh = cohorts.iter_mut()
+        .find(|c| c.species == species && c.age == 0.0 && c.parents == *parents && c.mother == mother);
+      if let Some(fresh) = fresh {
+        fresh.amount += amount;
+      } else {
+        cohorts.push(SeedCohort { species, amount, age: 0.0, chilling: 0.0, parents: parents.clone(), mother });
+      }
+      true
+    } else {
+      false
+    }
+  }
+}
+
+pub fn age_cohort(cohort: &mut SeedCohort, tree_type: &TreeType, tempreture: f32, elapsed_seconds: f32, settings: &Settings) {
+  let seeds = &tree_type.seed_bank;
+  cohort.age += elapsed_seconds;
+  if tempreture < seeds.stratification_tempreture {
+    cohort.chilling += year_days(elapsed_seconds, settings);
+  }
+  let half_life = seeds.half_life_years * settings.seconds_in_year();
+  cohort.amount *= 0.5_f32.powf(elapsed_seconds / half_life);
+}
+
+pub fn is_ready(cohort: &SeedCohort, tree_type: &TreeType, settings: &Settings) -> bool {
+  year_days(cohort.age, settings) >= tree_type.seed_bank.dormancy_days
+    && cohort.chilling >= tree_type.seed_bank.stratification_days
+}
+
+// Expected amount of seeds which germinate during elapsed time
+pub fn expected_germinants(
+  cohort: &SeedCohort,
+  tree_type: &TreeType,
+  season: Season,
+  elapsed_seconds: f32,
+  settings: &Settings
+) -> f32 {
+  if season != Season::Spring || !is_ready(cohort, tree_type, settings) {
+    return 0.0;
+  }
+  let days = year_days(elapsed_seconds, settings);
+  cohort.amount * (1.0 - (1.0 - tree_type.seed_bank.germination_rate).powf(days))
+}
+
+#[system]
+#[allow(clippy::too_many_arguments)]
+pub fn update_seed_bank(
+  commands: &mut CommandBuffer,
+  #[resource] bank: &mut SeedBank,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] weather: &Weather,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] grass: &GrassLayer,
+  #[resource] map: &Map,
+  #[resource] trees: &SpatialIndex<TreeProperties>,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
+) {
+  let cell_size = bank.0.cell_size();
+  for ix in 0..bank.0.len() {
+    let center = bank.0.cell_center(ix);
+    let ground_light = grass.light_at(&center);
+    let ground = map.ground_type(&center);
+    let cohorts = bank.0.cell_mut(ix);
+    for cohort in cohorts.iter_mut() {
+      let tree_type = registry.get(cohort.species);
+      age_cohort(cohort, tree_type, weather.current_tempreture, time.long.elapsed_seconds, settings);
+      if !can_germinate(tree_type, ground_light) {
+        continue;
+      }
+      let neighbours = trees.within_radius(&center, tree_type.germination_radius).count();
+      let suitability = site_suitability(tree_type, ground, neighbours);
+      let expected = suitability * expected_germinants(cohort, tree_type, time.current_season, time.long.elapsed_seconds, settings);
+      // Seedlings of this tick count towards the density too
+      let free = tree_type.germination_max_density.saturating_sub(neighbours) as f32;
+      let germinants = min(min(random_count(expected, rng) as f32, cohort.amount.floor()), free);
+      cohort.amount -= germinants;
+      for _ in 0..germinants as u32 {
+        let shift = Vector3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), 0.0) * cell_size;
+        let position = center + Position(shift);
+        let genome = cohort.parents.child(&tree_type.genetics, rng);
+        let entity = place_tree(commands, position, cohort.species, genome);
+        events.emit(Event::TreeSeeded { parent: cohort.mother, entity, position });
+      }
+    }
+    cohorts.retain(|cohort| cohort.amount >= MIN_SEEDS);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::grass::GrassConfig;
+  use num::traits::Zero;
+  use super::super::map::{ GroundType, polygon };
+  use super::super::phenology::DAYS_IN_YEAR;
+
+  fn oak() -> (SpeciesId, TreeType) {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    (oak, registry.get(oak).clone())
+  }
+
+  #[test]
+  fn seeds_need_winter_before_germination() {
+    let (species, oak) = oak();
+    let settings = Settings::default();
+    let day = settings.seconds_in_year() / DAYS_IN_YEAR;
+    let seeds = &oak.seed_bank;
+    let mut cohort = SeedCohort { species, amount: 100.0, age: 0.0, chilling: 0.0, parents: Parents::default(), mother: None };
+
+    age_cohort(&mut cohort, &oak, 20.0, seeds.dormancy_days * day, &settings);
+    assert_eq!(expected_germinants(&cohort, &oak, Season::Spring, day, &settings), 0.0);
+
+    age_cohort(&mut cohort, &oak, seeds.stratification_tempreture - 1.0, seeds.stratification_days * day, &settings);
+    assert_eq!(expected_germinants(&cohort, &oak, Season::Winter, day, &settings), 0.0);
+    assert!(expected_germinants(&cohort, &oak, Season::Spring, day, &settings) > 0.0);
+  }
+
+  #[test]
+  fn seeds_lose_viability() {
+    let (species, oak) = oak();
+    let settings = Settings::default();
+    let mut cohort = SeedCohort { species, amount: 100.0, age: 0.0, chilling: 0.0, parents: Parents::default(), mother: None };
+    age_cohort(&mut cohort, &oak, 20.0, oak.seed_bank.half_life_years * settings.seconds_in_year(), &settings);
+    assert!((cohort.amount - 50.0).abs() < 1e-3);
+  }
+
+  // Runs one spring day over the bank with ready seeds of the mother at the
+  // places, returns the world and the seeding events
+  fn germinate(map: Map, places: &[Position], trees: &[Position]) -> (World, Vec<Event>) {
+    let (species, oak) = oak();
+    let settings = Settings::default();
+    let mut world = World::default();
+    let mother = world.push((Position::zero(),));
+    let mut bank = SeedBank::covering(&map, 1.0);
+    for place in places {
+      assert!(bank.deposit(place, species, 1000.0, &Parents::default(), Some(mother)));
+    }
+    for cohort in bank.0.iter_mut().flatten() {
+      cohort.age = oak.seed_bank.dormancy_days / DAYS_IN_YEAR * settings.seconds_in_year();
+      cohort.chilling = oak.seed_bank.stratification_days;
+    }
+    let mut index = SpatialIndex::<TreeProperties>::new(1.0);
+    for position in trees {
+      index.insert(world.push((*position,)), position);
+    }
+
+    let mut timers = Timers { current_season: Season::Spring, ..Timers::default() };
+    timers.long.elapsed_seconds = settings.day_duration;
+    let mut weather = Weather::prepare("moderate");
+    weather.current_tempreture = 15.0;
+    let mut resources = Resources::default();
+    resources.insert(bank);
+    resources.insert(timers);
+    resources.insert(settings);
+    resources.insert(weather);
+    resources.insert(SpeciesRegistry::load());
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(map);
+    resources.insert(index);
+    resources.insert(SimRng::seeded(1));
+    resources.insert(Events::default());
+    let mut schedule = Schedule::builder()
+      .add_system(update_seed_bank_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let events = resources.get::<Events>().unwrap().iter().cloned().collect();
+    (world, events)
+  }
+
+  #[test]
+  fn ready_seeds_turn_into_trees_in_spring() {
+    let (species, oak) = oak();
+    let map = Map::test_square(10.0, 10.0);
+    let mut bank = SeedBank::covering(&map, 1.0);
+    assert!(!bank.deposit(&Position(Vector3::new(100.0, 0.0, 0.0)), species, 1000.0, &Parents::default(), None));
+
+    let (world, events) = germinate(map, &[Position::zero()], &[]);
+    let trees = <&TreeProperties>::query().iter(&world).count();
+    assert!(trees > 0);
+    assert!(trees <= oak.germination_max_density);
+    let mother = <(Entity, &Position)>::query().filter(!component::<TreeProperties>()).iter(&world).map(|(entity, _)| *entity).next();
+    assert!(events.iter().all(|event| matches!(event, Event::TreeSeeded { parent, .. } if *parent == mother)));
+  }
+
+  #[test]
+  fn seeds_germinate_only_on_free_soil() {
+    let (_, oak) = oak();
+    let mut map = Map::test_square(10.0, 10.0);
+    map.add_feature(GroundType::Asphalt, polygon(&[[1.0, 1.0], [3.0, 1.0], [3.0, 3.0], [1.0, 3.0]]));
+    let crowded = vec!(Position(Vector3::new(-2.5, -2.5, 0.0)); oak.germination_max_density);
+    let places = [Position(Vector3::new(1.5, 1.5, 0.0)), Position(Vector3::new(-2.5, -2.5, 0.0))];
+    let (world, _) = germinate(map, &places, &crowded);
+    assert_eq!(<&TreeProperties>::query().iter(&world).count(), 0);
+  }
+}
@@ -1,7 +1,23 @@
+use serde::{ Serialize, Deserialize };
+use super::growth::GrowthModel;
+use super::fruit_layer::FruitRepresentation;
+use super::phenology::{ PhenologyModel, Hemisphere };
+
+static SETTINGS: &str = include_str!("../config/settings.yaml");
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
   pub days_in_season: u16,
   pub day_duration: f32,
   pub climate_zone: String,
+  pub growth_model: GrowthModel,
+  pub keep_deadwood: bool, // dead trees are turned into deadwood instead of being removed
+  pub fruit_representation: FruitRepresentation,
+  pub phenology_model: PhenologyModel,
+  pub hemisphere: Hemisphere,
+  pub output_directory: Option<String>, // yearly metrics are only kept in memory without it
+  pub seed: Option<u64>, // of the random generator, every run differs without it
 }
 
 impl Default for Settings {
@@ -10,13 +26,49 @@ impl Default for Settings {
     Settings {
       days_in_season: 10, // 10 days per season
       day_duration: 12.0 * seconds_per_hour, // 12 hours pre day
-      climate_zone: "moderate".into()
+      climate_zone: "moderate".into(),
+      growth_model: GrowthModel::ResourceBased,
+      keep_deadwood: true,
+      fruit_representation: FruitRepresentation::Entities,
+      phenology_model: PhenologyModel::DegreeDays,
+      hemisphere: Hemisphere::Northern,
+      output_directory: None,
+      seed: None,
     }
   }
 }
 
 impl Settings {
+  // Settings of the simulation run, keys left out keep their defaults
+  pub fn load() -> Self {
+    serde_yaml::from_str(SETTINGS).unwrap()
+  }
+
   pub fn seconds_in_year(&self) -> f32 {
     (self.days_in_season as f32) * 4.0 * self.day_duration
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn alternative_models_are_selected_by_config() {
+    let settings = Settings::load();
+    assert_eq!(settings.seconds_in_year(), 2.0 * 4.0 * 60.0);
+    let settings: Settings = serde_yaml::from_str("
+      growth_model: simple
+      fruit_representation: layer
+      phenology_model: calendar
+      hemisphere: southern
+      seed: 7
+    ").unwrap();
+    assert_eq!(settings.growth_model, GrowthModel::Simple);
+    assert_eq!(settings.fruit_representation, FruitRepresentation::Layer);
+    assert_eq!(settings.phenology_model, PhenologyModel::Calendar);
+    assert_eq!(settings.hemisphere, Hemisphere::Southern);
+    assert_eq!(settings.seed, Some(7));
+    assert_eq!(settings.day_duration, Settings::default().day_duration);
+  }
+}
@@ -0,0 +1,278 @@
+use legion::*;
+use legion::storage::Component;
+use legion::world::SubWorld;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use super::movements::{ Position, Vector3 };
+
+type Cell = (i32, i32);
+
+// Uniform grid over x/y plane. Items are bucketed by the cell their position
+// falls into, so queries only visit cells around the point.
+pub struct SpatialGrid<T> {
+  cell_size: f32,
+  cells: HashMap<Cell, Vec<(Vector3, T)>>,
+  len: usize,
+}
+
+impl<T> SpatialGrid<T> {
+  pub fn new(cell_size: f32) -> Self {
+    SpatialGrid {
+      cell_size,
+      cells: HashMap::new(),
+      len: 0
+    }
+  }
+
+  fn cell(&self, point: &Vector3) -> Cell {
+    ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32)
+  }
+
+  pub fn clear(&mut self) {
+    self.cells.clear();
+    self.len = 0;
+  }
+
+  pub fn insert(&mut self, position: &Position, item: T) {
+    let cell = self.cell(&position.0);
+    self.cells.entry(cell).or_default().push((position.0, item));
+    self.len += 1;
+  }
+
+  fn cells_between<'a>(&'a self, min: Cell, max: Cell) -> impl Iterator<Item = &'a (Vector3, T)> + 'a {
+    (min.0..=max.0)
+      .flat_map(move |x| (min.1..=max.1).map(move |y| (x, y)))
+      .filter_map(move |cell| self.cells.get(&cell))
+      .flat_map(|items| items.iter())
+  }
+
+  pub fn within_radius<'a>(&'a self, position: &Position, radius: f32) -> impl Iterator<Item = &'a T> + 'a {
+    let center = position.0;
+    let min = self.cell(&(center - Vector3::new(radius, radius, 0.0)));
+    let max = self.cell(&(center + Vector3::new(radius, radius, 0.0)));
+    self.cells_between(min, max)
+      .filter(move |(point, _)| distance_2d(point, &center) <= radius)
+      .map(|(_, item)| item)
+  }
+
+  pub fn within_rect<'a>(&'a self, min: &Position, max: &Position) -> impl Iterator<Item = &'a T> + 'a {
+    let (low, high) = (min.0, max.0);
+    self.cells_between(self.cell(&low), self.cell(&high))
+      .filter(move |(point, _)| point.x >= low.x && point.x <= high.x && point.y >= low.y && point.y <= high.y)
+      .map(|(_, item)| item)
+  }
+
+  // Visits rings of cells around the point until k items are found and no
+  // unvisited cell can contain anything closer.
+  pub fn nearest(&self, position: &Position, k: usize) -> Vec<&T> {
+    let center = self.cell(&position.0);
+    let mut found: Vec<(f32, &T)> = Vec::new();
+    let mut ring = 0;
+    while k > 0 && found.len() < self.len {
+      for cell in ring_cells(center, ring) {
+        if let Some(items) = self.cells.get(&cell) {
+          found.extend(items.iter().map(|(point, item)| (distance_2d(point, &position.0), item)));
+        }
+      }
+      found.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+      if found.len() >= k && found[k - 1].0 <= ring as f32 * self.cell_size {
+        break;
+      }
+      ring += 1;
+    }
+    found.into_iter().take(k).map(|(_, item)| item).collect()
+  }
+}
+
+fn ring_cells(center: Cell, ring: i32) -> Vec<Cell> {
+  if ring == 0 {
+    return vec!(center);
+  }
+  let mut cells = Vec::with_capacity(8 * ring as usize);
+  for d in -ring..=ring {
+    cells.push((center.0 + d, center.1 - ring));
+    cells.push((center.0 + d, center.1 + ring));
+  }
+  for d in -ring + 1..ring {
+    cells.push((center.0 - ring, center.1 + d));
+    cells.push((center.0 + ring, center.1 + d));
+  }
+  cells
+}
+
+pub fn distance_2d(a: &Vector3, b: &Vector3) -> f32 {
+  ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+// Index of entities which have both Position and component T. It is rebuilt
+// by update_spatial_index system on every slow tick.
+pub struct SpatialIndex<T> {
+  grid: SpatialGrid<Entity>,
+  component: PhantomData<fn() -> T>,
+}
+
+impl<T> SpatialIndex<T> {
+  pub fn new(cell_size: f32) -> Self {
+    SpatialIndex {
+      grid: SpatialGrid::new(cell_size),
+      component: PhantomData
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.grid.clear();
+  }
+
+  pub fn insert(&mut self, entity: Entity, position: &Position) {
+    self.grid.insert(position, entity);
+  }
+
+  pub fn within_radius<'a>(&'a self, position: &Position, radius: f32) -> impl Iterator<Item = Entity> + 'a {
+    self.grid.within_radius(position, radius).copied()
+  }
+
+  pub fn within_rect<'a>(&'a self, min: &Position, max: &Position) -> impl Iterator<Item = Entity> + 'a {
+    self.grid.within_rect(min, max).copied()
+  }
+
+  pub fn nearest(&self, position: &Position, k: usize) -> Vec<Entity> {
+    self.grid.nearest(position, k).into_iter().copied().collect()
+  }
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(T)]
+pub fn update_spatial_index<T: Component>(world: &mut SubWorld, #[resource] index: &mut SpatialIndex<T>) {
+  index.clear();
+  let mut query = <(Entity, &Position, &T)>::query();
+  for (entity, position, _) in query.iter(world) {
+    index.insert(*entity, position);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rand::Rng;
+  use std::time::Instant;
+  use super::super::random::SimRng;
+
+  struct Marker;
+
+  fn point(x: f32, y: f32) -> Position {
+    Position(Vector3::new(x, y, 0.0))
+  }
+
+  fn test_grid() -> SpatialGrid<i32> {
+    let mut grid = SpatialGrid::new(2.0);
+    grid.insert(&point(0.0, 0.0), 1);
+    grid.insert(&point(1.5, 1.5), 2);
+    grid.insert(&point(-3.0, 0.0), 3);
+    grid.insert(&point(10.0, 10.0), 4);
+    grid
+  }
+
+  fn sorted<'a>(items: impl Iterator<Item = &'a i32>) -> Vec<i32> {
+    let mut items: Vec<i32> = items.cloned().collect();
+    items.sort();
+    items
+  }
+
+  #[test]
+  fn radius_query_returns_only_close_items() {
+    let grid = test_grid();
+    assert_eq!(sorted(grid.within_radius(&point(0.0, 0.0), 3.0)), vec!(1, 2, 3));
+  }
+
+  #[test]
+  fn rect_query_returns_items_inside() {
+    let grid = test_grid();
+    assert_eq!(sorted(grid.within_rect(&point(-1.0, -1.0), &point(11.0, 11.0))), vec!(1, 2, 4));
+  }
+
+  #[test]
+  fn nearest_are_sorted_by_distance() {
+    let grid = test_grid();
+    assert_eq!(grid.nearest(&point(9.0, 9.0), 2), vec!(&4, &2));
+    assert_eq!(grid.nearest(&point(0.0, 0.0), 10).len(), 4);
+    assert!(grid.nearest(&point(0.0, 0.0), 0).is_empty());
+  }
+
+  #[test]
+  fn nearest_matches_linear_search() {
+    let mut rng = SimRng::seeded(7);
+    let points: Vec<Position> = (0..500)
+      .map(|_| point(rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0)))
+      .collect();
+    let mut grid = SpatialGrid::new(3.0);
+    for (ix, p) in points.iter().enumerate() {
+      grid.insert(p, ix);
+    }
+    let target = point(12.0, -7.0);
+    let mut linear: Vec<usize> = (0..points.len()).collect();
+    linear.sort_by(|a, b| {
+      distance_2d(&points[*a].0, &target.0).partial_cmp(&distance_2d(&points[*b].0, &target.0)).unwrap()
+    });
+    let nearest: Vec<usize> = grid.nearest(&target, 5).into_iter().copied().collect();
+    assert_eq!(nearest, linear[..5].to_vec());
+  }
+
+  #[test]
+  fn index_contains_only_entities_with_component() {
+    let mut world = World::default();
+    let mut resources = Resources::default();
+    resources.insert(SpatialIndex::<Marker>::new(1.0));
+    let marked = world.push((point(0.0, 0.0), Marker));
+    world.push((point(0.5, 0.0),));
+    let mut schedule = Schedule::builder()
+      .add_system(update_spatial_index_system::<Marker>())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let index = resources.get::<SpatialIndex<Marker>>().unwrap();
+    assert_eq!(index.within_radius(&point(0.0, 0.0), 1.0).collect::<Vec<_>>(), vec!(marked));
+  }
+
+  // cargo test --release -- --ignored bench_spatial_index --nocapture
+  #[test]
+  #[ignore]
+  fn bench_spatial_index() {
+    let amount = 100_000;
+    let queries = 10_000;
+    let mut rng = SimRng::seeded(1);
+    let mut world = World::default();
+    let mut resources = Resources::default();
+    resources.insert(SpatialIndex::<Marker>::new(5.0));
+    world.extend((0..amount).map(|_| (point(rng.gen_range(-500.0..500.0), rng.gen_range(-500.0..500.0)), Marker)));
+    let mut schedule = Schedule::builder()
+      .add_system(update_spatial_index_system::<Marker>())
+      .build();
+
+    let started = Instant::now();
+    schedule.execute(&mut world, &mut resources);
+    println!("index rebuild of {} entities: {:?}", amount, started.elapsed());
+
+    let index = resources.get::<SpatialIndex<Marker>>().unwrap();
+    let targets: Vec<Position> = (0..queries)
+      .map(|_| point(rng.gen_range(-500.0..500.0), rng.gen_range(-500.0..500.0)))
+      .collect();
+
+    let started = Instant::now();
+    let found: usize = targets.iter().map(|p| index.within_radius(p, 10.0).count()).sum();
+    let indexed = started.elapsed() / queries;
+    println!("{} radius queries: {:?}, found {}", queries, started.elapsed(), found);
+
+    let started = Instant::now();
+    let found: usize = targets.iter().map(|p| index.nearest(p, 8).len()).sum();
+    println!("{} 8-nearest queries: {:?}, found {}", queries, started.elapsed(), found);
+
+    let started = Instant::now();
+    let found: usize = targets.iter().take(100).map(|target| {
+      <&Position>::query().iter(&world).filter(|p| distance_2d(&p.0, &target.0) <= 10.0).count()
+    }).sum();
+    let linear = started.elapsed() / 100;
+    println!("100 radius queries by linear iteration: {:?}, found {}", started.elapsed(), found);
+    assert!(indexed * 10 < linear, "indexed query {:?}, linear {:?}", indexed, linear);
+  }
+}
@@ -0,0 +1,46 @@
+use super::tree::{ TreeConfig, TreeType };
+
+// Compact per-entity reference into SpeciesRegistry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SpeciesId(pub u16);
+
+pub struct SpeciesRegistry(Vec<TreeType>);
+
+impl SpeciesRegistry {
+  pub fn load() -> Self {
+    Self::from_config(TreeConfig::load())
+  }
+
+  pub fn from_config(config: TreeConfig) -> Self {
+    SpeciesRegistry(config.spieces)
+  }
+
+  pub fn get(&self, id: SpeciesId) -> &TreeType {
+    &self.0[id.0 as usize]
+  }
+
+  pub fn find(&self, name: &str) -> Option<SpeciesId> {
+    self.0.iter()
+      .position(|tree_type| tree_type.name == name)
+      .map(|ix| SpeciesId(ix as u16))
+  }
+
+  #[cfg(test)]
+  pub fn iter(&self) -> impl Iterator<Item = (SpeciesId, &TreeType)> {
+    self.0.iter().enumerate().map(|(ix, tree_type)| (SpeciesId(ix as u16), tree_type))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn species_are_found_by_name() {
+    let registry = SpeciesRegistry::load();
+    let oak = registry.find("oak").unwrap();
+    assert_eq!(registry.get(oak).name, "oak");
+    assert_eq!(registry.find("baobab"), None);
+    assert_eq!(registry.iter().count(), 6);
+  }
+}
@@ -0,0 +1,243 @@
+use legion::*;
+use serde::{ Serialize, Deserialize };
+use super::timer::Timers;
+use super::settings::Settings;
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::tree::{ TreeType, TreeProperties, clamp };
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ShadeTolerance {
+  pub tolerance: f32, // 0 grows only in full sun, 1 grows as well in deep shade
+  pub min_light: f32, // trees getting less light are suppressed
+  pub survival_years: f32, // of suppression the tree survives
+}
+
+// Species colonizing open ground after disturbance
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Pioneer {
+  pub gap_light: f32, // seeds in the soil germinate only where this much light reaches the ground
+}
+
+// Part of full sun growth the tree achieves at the light. Tolerant trees make
+// use of dim light, intolerant ones need full sun.
+pub fn light_response(light: f32, shade: &ShadeTolerance) -> f32 {
+  clamp(light, 0.0, 1.0).powf(1.0 - clamp(shade.tolerance, 0.0, 1.0))
+}
+
+// Suppression is forgiven as soon as the tree gets out of the shade
+pub fn update_suppression(properties: &mut TreeProperties, shade: &ShadeTolerance, elapsed_years: f32) {
+  if properties.light < shade.min_light {
+    properties.suppressed_years += elapsed_years;
+  } else {
+    properties.suppressed_years = 0.0;
+  }
+}
+
+pub fn dies_of_shade(properties: &TreeProperties, tree_type: &TreeType) -> bool {
+  properties.suppressed_years > tree_type.shade.survival_years
+}
+
+// Pioneers wait for a gap, other species germinate anywhere. Ground light
+// is the one left to grass under leafy crowns.
+pub fn can_germinate(tree_type: &TreeType, ground_light: f32) -> bool {
+  tree_type.pioneer.as_ref().is_none_or(|pioneer| ground_light >= pioneer.gap_light)
+}
+
+#[system(for_each)]
+pub fn update_shade_stress(
+  species: &SpeciesId,
+  properties: &mut TreeProperties,
+  #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+) {
+  let elapsed_years = time.long.elapsed_seconds / settings.seconds_in_year();
+  update_suppression(properties, &registry.get(*species).shade, elapsed_years);
+}
+
+#[cfg(test)]
+mod test {
+  use num::traits::Zero;
+  use super::*;
+  use super::super::timer::Season;
+  use super::super::map::Map;
+  use super::super::movements::{ Position, Vector3 };
+  use super::super::allometry::Dimensions;
+  use super::super::climate::{ Weather, weather_system };
+  use super::super::drought::{ WaterConfig, update_water_system };
+  use super::super::events::{ Events, clear_events_system };
+  use super::super::random::SimRng;
+  use super::super::spatial::{ SpatialIndex, update_spatial_index_system };
+  use super::super::light::update_light_system;
+  use super::super::grass::{ GrassLayer, GrassConfig, update_grass_system };
+  use super::super::pollination::pollinate_system;
+  use super::super::growth::grow_trees_system;
+  use super::super::allometry::update_dimensions_system;
+  use super::super::mortality::mortality_system;
+  use super::super::fruit::fruit_lifecycle_system;
+  use super::super::fruit_layer::FruitLayer;
+  use super::super::seed_bank::{ SeedBank, update_seed_bank_system };
+  use super::super::inventory::basal_area;
+  use super::super::tree::{
+    TreeState,
+    place_tree_test,
+    update_trees_system,
+    update_offspring_system,
+    drop_fruits_system
+  };
+
+  const TICKS_PER_DAY: usize = 2;
+
+  fn species(name: &str) -> TreeType {
+    let registry = SpeciesRegistry::load();
+    registry.get(registry.find(name).unwrap()).clone()
+  }
+
+  #[test]
+  fn tolerant_trees_grow_in_shade() {
+    let (birch, beech) = (species("birch"), species("beech"));
+    assert_eq!(light_response(1.0, &birch.shade), 1.0);
+    assert_eq!(light_response(1.0, &beech.shade), 1.0);
+    assert!(light_response(0.2, &beech.shade) > 2.0 * light_response(0.2, &birch.shade));
+  }
+
+  #[test]
+  fn suppressed_trees_die() {
+    let birch = species("birch");
+    let mut tree = TreeProperties::new(0.0, 1.0);
+    tree.light = birch.shade.min_light / 2.0;
+    update_suppression(&mut tree, &birch.shade, birch.shade.survival_years);
+    assert!(!dies_of_shade(&tree, &birch));
+    update_suppression(&mut tree, &birch.shade, 1.0);
+    assert!(dies_of_shade(&tree, &birch));
+    tree.light = 1.0;
+    update_suppression(&mut tree, &birch.shade, 1.0);
+    assert_eq!(tree.suppressed_years, 0.0);
+  }
+
+  #[test]
+  fn pioneers_germinate_in_gaps() {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    let beech = place_tree_test(&mut world, &registry, "beech", Position::zero(), 0.0, 10.0).unwrap();
+    let mut entry = world.entry(beech).unwrap();
+    entry.get_component_mut::<TreeProperties>().unwrap().state = TreeState::Ripening;
+    let radius = entry.get_component::<Dimensions>().unwrap().crown_radius;
+    let map = Map::test_square(40.0, 40.0);
+    let mut resources = Resources::default();
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(Timers::default());
+    resources.insert(Settings::default());
+    resources.insert(Weather::prepare("moderate"));
+    resources.insert(registry);
+    let mut schedule = Schedule::builder()
+      .add_system(update_grass_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+
+    let grass = resources.get::<GrassLayer>().unwrap();
+    let under = grass.light_at(&Position::zero());
+    let open = grass.light_at(&Position(Vector3::new(radius + 2.0, 0.0, 0.0)));
+    assert!((under - 0.1).abs() < 1e-6);
+    assert_eq!(open, 1.0);
+    assert!(!can_germinate(&species("birch"), under));
+    assert!(can_germinate(&species("birch"), open));
+    assert!(can_germinate(&species("beech"), under));
+  }
+
+  // Basal area of birch and beech, in square meters, after every year of a
+  // stand growing from a few founders on open ground. The clock is driven by
+  // the test in half days, everything else runs as in the simulation.
+  fn succession(seed: u64, years: usize) -> Vec<(f32, f32)> {
+    let settings = Settings {
+      days_in_season: 2,
+      day_duration: 1.0,
+      keep_deadwood: false,
+      ..Settings::default()
+    };
+    let ticks_per_year = 4 * settings.days_in_season as usize * TICKS_PER_DAY;
+    let tick = settings.day_duration / TICKS_PER_DAY as f32;
+    let map = Map::test_square(30.0, 30.0);
+    let registry = SpeciesRegistry::load();
+    let (birch, beech) = (registry.find("birch").unwrap(), registry.find("beech").unwrap());
+    let mut world = World::default();
+    for (name, x, y, age_years) in [
+      ("birch", -12.0, -12.0, 10.0), ("birch", -12.0, 12.0, 10.0), ("birch", 12.0, -12.0, 10.0),
+      ("beech", -3.0, -3.0, 1.0), ("beech", 3.0, -3.0, 1.0), ("beech", -3.0, 3.0, 1.0), ("beech", 3.0, 3.0, 1.0),
+    ] {
+      let position = Position(Vector3::new(x, y, 0.0));
+      place_tree_test(&mut world, &registry, name, position, age_years * settings.seconds_in_year(), 1.0).unwrap();
+    }
+
+    let mut resources = Resources::default();
+    resources.insert(SeedBank::covering(&map, 1.0));
+    resources.insert(FruitLayer::covering(&map, 1.0));
+    resources.insert(GrassLayer::covering(&map, 1.0, GrassConfig::load()));
+    resources.insert(SpatialIndex::<TreeProperties>::new(5.0));
+    resources.insert(Timers::default());
+    resources.insert(Weather::prepare(&settings.climate_zone));
+    resources.insert(WaterConfig::load());
+    resources.insert(Events::default());
+    resources.insert(SimRng::seeded(seed));
+    resources.insert(settings.clone());
+    resources.insert(registry);
+    resources.insert(map);
+    let mut schedule = Schedule::builder()
+      .add_system(clear_events_system())
+      .add_system(weather_system())
+      .add_system(update_spatial_index_system::<TreeProperties>())
+      .add_system(update_light_system())
+      .add_system(update_grass_system())
+      .add_system(update_water_system())
+      .add_system(pollinate_system())
+      .add_system(update_trees_system())
+      .add_system(grow_trees_system())
+      .add_system(update_shade_stress_system())
+      .add_system(update_dimensions_system())
+      .add_system(mortality_system())
+      .add_system(update_offspring_system())
+      .add_system(drop_fruits_system())
+      .add_system(fruit_lifecycle_system())
+      .add_system(update_seed_bank_system())
+      .build();
+
+    let mut trajectory = Vec::new();
+    for _ in 0..years {
+      for step in 0..ticks_per_year {
+        {
+          let mut timers = resources.get_mut::<Timers>().unwrap();
+          timers.long.elapsed_seconds = tick;
+          timers.time_of_year = step as f32 / ticks_per_year as f32;
+          timers.time_of_day = (step % TICKS_PER_DAY) as f32 / TICKS_PER_DAY as f32;
+          timers.current_season = Season::from((timers.time_of_year * 4.0) as u16);
+        }
+        schedule.execute(&mut world, &mut resources);
+      }
+      let mut stand = (0.0, 0.0);
+      for (species, dimensions, _) in <(&SpeciesId, &Dimensions, &TreeProperties)>::query().iter(&world) {
+        if *species == birch {
+          stand.0 += basal_area(dimensions);
+        } else if *species == beech {
+          stand.1 += basal_area(dimensions);
+        }
+      }
+      trajectory.push(stand);
+    }
+    trajectory
+  }
+
+  // runs a century and a half of the stand, slow in debug builds
+  // cargo test --release -- --ignored pioneers_are_replaced_by_shade_tolerant_trees
+  #[test]
+  #[ignore]
+  fn pioneers_are_replaced_by_shade_tolerant_trees() {
+    let trajectory = succession(4, 150);
+    let share = |(birch, beech): (f32, f32)| birch / (birch + beech);
+    let birch_peak = (0..trajectory.len()).max_by(|a, b| trajectory[*a].0.total_cmp(&trajectory[*b].0)).unwrap();
+    let beech_peak = (0..trajectory.len()).max_by(|a, b| trajectory[*a].1.total_cmp(&trajectory[*b].1)).unwrap();
+    assert!(share(trajectory[20]) > 0.7);
+    assert!(share(trajectory[149]) < 0.5);
+    assert!(birch_peak < beech_peak);
+    assert_eq!(trajectory[..20], succession(4, 20)[..]);
+  }
+}
@@ -1,6 +1,7 @@
 use legion::*;
 use std::time::SystemTime;
 use super::settings::Settings;
+use super::events::{ Events, Event };
 
 
 pub struct Time {
@@ -8,6 +9,7 @@ pub struct Time {
   last_timestamp: SystemTime
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Season {
   Autumn,
   Summer,
@@ -37,6 +39,8 @@ impl Timers {
     self.time_of_year = (elapsed_seconds % total_year) / total_year;
     self.time_of_season = (elapsed_seconds % total_season) / total_season;
     self.time_of_day = (elapsed_seconds % settings.day_duration) / settings.day_duration;
+    let local_year_time = settings.hemisphere.local_year_time(self.time_of_year);
+    self.current_season = Season::from((local_year_time * 4.0) as u16 % 4);
     self.long.elapsed_seconds = self.long.last_timestamp.elapsed().map(|dur| dur.as_secs_f32()).unwrap_or(0.0);
     self.long.last_timestamp = SystemTime::now();
     /*
@@ -112,6 +116,14 @@ pub fn update_long_times(resources: &mut Resources) {
 }
 
 #[system]
-pub fn timer_update(#[resource] timers: &mut Timers, #[resource] settings: &Settings) {
-  timers.update_fields(settings)
+pub fn timer_update(
+  #[resource] timers: &mut Timers,
+  #[resource] settings: &Settings,
+  #[resource] events: &mut Events,
+) {
+  let previous_season = timers.current_season;
+  timers.update_fields(settings);
+  if previous_season != timers.current_season {
+    events.emit(Event::SeasonChanged { from: previous_season, to: timers.current_season });
+  }
 }
@@ -3,29 +3,49 @@ use legion::systems::CommandBuffer;
 use super::timer::Timers;
 use super::settings::Settings;
 use serde::{ Serialize, Deserialize };
-use super::interpolations::lerp_map;
-use super::climate::Weather;
-use super::movements::{ Vector3, Position };
+use super::climate::{ Weather, Wind };
+use super::movements::Position;
 use super::map::Map;
-use std::ops::{Sub, Add, Div};
+use super::events::{ Events, Event };
+use super::species::{ SpeciesId, SpeciesRegistry };
+use super::growth::{ GrowEffectiviness, GrowthModel, pay_for_transition };
+use super::dispersal::{ DispersalKernel, disperse };
+use super::allometry::{ Allometry, Dimensions, crown_radius };
+use super::random::SimRng;
+use super::fruit::Fruit;
+use super::seed_bank::{ SeedBank, SeedDormancy };
+use super::fruit_layer::{ FruitLayer, FruitRepresentation };
+use super::genetics::{ Genome, GeneticsConfig, pick_parents };
+use super::life_form::{ LifeForm, ClonalSpread, die_back };
+use super::mortality::{ DeathCause, kill_tree };
+use super::drought::{ DroughtTolerance, water_limitation, early_leaf_fall };
+use super::pollination::{ Pollination, reset_pollination };
+use super::succession::{ ShadeTolerance, Pioneer };
+use super::phenology::{
+  PhenologyModel,
+  Phases,
+  Hemisphere,
+  DegreeDayPhenology,
+  accumulate_thermal_time,
+  degree_day_state,
+  degree_day_maturity,
+  start_dormancy
+};
+use std::ops::{Sub, Add};
+use rand::Rng;
 use rand::distributions::{ uniform::SampleUniform, Distribution, Uniform};
-use num::traits::Zero;
+use std::collections::HashMap;
 
 static TREES_CONFIG: &str = include_str!("../config/trees.yaml");
 
-#[derive(Clone, Serialize, Deserialize)]
-struct GrowEffectiviness {
-  starting_temp: f32,
-  ending_temp: f32,
-  distribution: Vec<f32>
-}
-
 pub struct Offspring {
   pub amount: u16,
   pub maturity: f32,
+  pub pollen: Vec<Genome>, // genomes of neighbours which have pollinated the flowers
+  pub lost: f32, // part of a fruit lost to disease, carried to the next tick
 }
 
-fn max<T: PartialOrd>(v: T, max: T) -> T {
+pub fn max<T: PartialOrd>(v: T, max: T) -> T {
   if v > max {
     v
   } else {
@@ -33,7 +53,7 @@ fn max<T: PartialOrd>(v: T, max: T) -> T {
   }
 }
 
-fn min<T: PartialOrd>(v: T, min: T) -> T {
+pub fn min<T: PartialOrd>(v: T, min: T) -> T {
   if v < min {
     v
   } else {
@@ -41,62 +61,110 @@ fn min<T: PartialOrd>(v: T, min: T) -> T {
   }
 }
 
-fn clamp<T: PartialOrd>(v: T, minimun: T, maximum: T) -> T {
+pub fn clamp<T: PartialOrd>(v: T, minimun: T, maximum: T) -> T {
   max(min(v, maximum), minimun)
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TreeType {
-  name: String,
+  pub name: String,
   fruit_name: Option<String>,
-  maturity_years: f32,
-  blossom_start_year_time: f32,
-  ripening_start_year_time: f32,
-  fall_start_year_time: f32,
-  sleep_start_year_time: f32,
+  pub maturity_years: f32,
+  pub life_form: LifeForm,
+  pub clonal_spread: Option<ClonalSpread>,
+  pub phases: Phases, // drive states only with calendar phenology
+  pub phase_offset: f32, // part of the year all phases are delayed by
   fertility: f32, // how much trees will grow next year
   fruit_amount: f32, // how much average grown tree gives
-  growth_speed: f32,
-  max_size: f32
+  pub growth_speed: f32,
+  pub max_size: f32,
+  pub effectiveness: GrowEffectiviness,
+  pub resource_gain: f32, // resources per second at best tempreture on fertile soil
+  pub growth_allocation: f32, // part of gained resources invested into size
+  pub size_cost: f32, // resources per unit of size
+  pub blossom_cost: f32,
+  pub ripening_cost: f32,
+  pub maintenance_cost: f32, // resources per unit of size per second
+  pub frost_tempreture: f32,
+  pub negative_tempreture_survival: f32, // days below frost_tempreture
+  pub starvation_survival: f32, // days without resources
+  pub max_age_years: f32,
+  pub background_mortality: f32, // yearly probability to die
+  pub susceptibility: HashMap<String, f32>, // pathogen name -> how easily it infects and harms the tree
+  pub drought: DroughtTolerance,
+  pub shade: ShadeTolerance,
+  pub pioneer: Option<Pioneer>,
+  pub allometry: Allometry,
+  pub crown_opacity: f32, // part of light intercepted by crown
+  pub dispersal: DispersalKernel,
+  pub fruit_nutrition: f32,
+  pub fruit_lifetime_days: f32,
+  pub fruit_predation: f32,
+  pub fruit_caching: f32,
+  pub germination_rate: f32, // daily probability to germinate in spring
+  pub germination_radius: f32,
+  pub germination_max_density: usize, // trees within germination_radius which prevent germination
+  pub seed_bank: SeedDormancy,
+  pub pollination: Pollination,
+  pub genetics: GeneticsConfig,
+  pub phenology: DegreeDayPhenology,
 }
 
 impl TreeType {
-  fn offspring(&self, props: &TreeProperties) -> Offspring {
+  // Probabilities should stay within 0.0 and 1.0
+  pub fn validate(&self) -> Result<(), String> {
+    let rates = [
+      ("background_mortality", self.background_mortality),
+      ("fruit_caching", self.fruit_caching),
+      ("germination_rate", self.germination_rate),
+      ("seed_bank.germination_rate", self.seed_bank.germination_rate),
+    ];
+    match rates.iter().find(|(_, rate)| !(0.0..=1.0).contains(rate)) {
+      Some((name, rate)) => Err(format!("{} should be from 0 to 1, got {}", name, rate)),
+      None => Ok(())
+    }
+  }
+
+  // Time of the year as seen by phases of this species
+  pub fn year_time(&self, time_of_year: f32, hemisphere: Hemisphere, genome: &Genome) -> f32 {
+    genome.year_time(time_of_year - hemisphere.year_offset() - self.phase_offset)
+  }
+
+  fn offspring(&self, props: &TreeProperties, genome: &Genome) -> Offspring {
     let size_mul = props.size / self.max_size;
-    let amount = self.fruit_amount * size_mul;
+    let amount = self.fruit_amount * size_mul * props.light * water_limitation(props, self) * props.fruit_set * genome.fertility;
     Offspring {
       amount: amount as u16,
-      maturity: 0.0
+      maturity: 0.0,
+      pollen: props.pollen.clone(),
+      lost: 0.0
     }
   }
 }
 
-struct Fruit {
-  name: String
-}
-
-fn map_to_effectiveness(temp: f32, effectiveness: &GrowEffectiviness) -> f32 {
-  let t = clamp(
-    (temp - effectiveness.starting_temp) / (effectiveness.ending_temp - effectiveness.starting_temp),
-    0.0, 1.0
-  );
-  lerp_map(t, &effectiveness.distribution)
-}
-
-
 #[derive(Serialize, Deserialize)]
 pub struct TreeConfig {
-  spieces: Vec<TreeType>
+  pub spieces: Vec<TreeType>
 }
 
 impl TreeConfig {
-  fn load() -> Self {
-    serde_yaml::from_str(TREES_CONFIG).unwrap()
+  pub fn load() -> Self {
+    let config: TreeConfig = serde_yaml::from_str(TREES_CONFIG).unwrap();
+    for tree_type in &config.spieces {
+      if let Err(error) = tree_type.phases.validate() {
+        panic!("wrong phases of {}: {}", tree_type.name, error);
+      }
+      if let Err(error) = tree_type.validate() {
+        panic!("wrong rates of {}: {}", tree_type.name, error);
+      }
+    }
+    config
   }
 }
 
-#[derive(PartialEq, Clone, Debug)]
-enum TreeState{
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TreeState{
   Sleep,
   Blossom,
   Ripening,
@@ -104,57 +172,66 @@ enum TreeState{
 }
 
 pub struct TreeProperties {
-  size: f32,
-  age: f32,
-  resources: f32,
-  state: TreeState,
-  negative_temprature_constant_time: f32
+  pub size: f32,
+  pub age: f32,
+  pub resources: f32,
+  pub state: TreeState,
+  pub phase: usize, // index into phases of the species, first phase of the state with degree-days
+  pub negative_temprature_constant_time: f32,
+  pub starvation_time: f32,
+  pub light: f32, // light availability after shading by neighbours
+  pub vigour: f32, // part of growth left to the tree by pests and diseases
+  pub water: f32, // water status from 0 when dry to 1 when wet
+  pub drought_days: f32, // days of a real year spent with too little water
+  pub fruit_set: f32, // part of flowers pollinated during the last blossom
+  pub blossom_time: f32, // seconds of blossom pollination was counted for
+  pub suppressed_years: f32, // spent in shade below min_light of the species
+  pub chilling_days: f32, // chilling gathered since the tree went to sleep
+  pub degree_days: f32, // growing degree-days gathered since dormancy was broken
+  pub cooling: f32, // degree-days below fall tempreture gathered while ripening
+  pub pollen: Vec<Genome>, // genomes of neighbours which pollinated the last blossom
+  pub dead: bool, // killed during this tick, other causes of death skip the tree
 }
 
-fn select_state(t: f32, tree: &TreeType) -> TreeState {
-  use TreeState::*;
-  let thresholds = [
-    tree.sleep_start_year_time - 1.0,
-    tree.blossom_start_year_time, 
-    tree.ripening_start_year_time,
-    tree.fall_start_year_time,
-    tree.sleep_start_year_time,
-    tree.blossom_start_year_time + 1.0,
-  ];
-
-  for i in 0..thresholds.len() - 1 {
-    let min = thresholds[i];
-    let max = thresholds[i+1];
-    if t >= min && t < max {
-      return match i {
-        0 => Sleep,
-        1 => Blossom,
-        2 => Ripening,
-        3 => Falling,
-        4 => Sleep,
-        _ => { panic!("wrong choice"); }
-      };
-    } else {
+impl TreeProperties {
+  pub fn new(age: f32, size: f32) -> Self {
+    TreeProperties {
+      age,
+      resources: 20.0,
+      size,
+      state: TreeState::Sleep,
+      phase: 0,
+      negative_temprature_constant_time: 0.0,
+      starvation_time: 0.0,
+      light: 1.0,
+      vigour: 1.0,
+      water: 1.0,
+      drought_days: 0.0,
+      fruit_set: 1.0,
+      blossom_time: 0.0,
+      suppressed_years: 0.0,
+      chilling_days: 0.0,
+      degree_days: 0.0,
+      cooling: 0.0,
+      pollen: Vec::new(),
+      dead: false,
     }
   }
-
-  panic!("time out of range {}", t);
 }
 
-fn state_transition(year_time: f32, tree: &TreeType) -> TreeState {
-  select_state(year_time, tree)
+fn select_phase(year_time: f32, tree: &TreeType) -> usize {
+  tree.phases.select(year_time)
 }
 
-fn inmature_state_transition(year_time: f32, tree: &TreeType) -> TreeState {
-  use TreeState::*;
-  match select_state(year_time, tree) {
-    Blossom => {
-      Ripening
-    },
+// Inmature trees do not blossom
+fn inmature(state: TreeState) -> TreeState {
+  match state {
+    TreeState::Blossom => TreeState::Ripening,
     state => state
   }
 }
 
+
 /* OLD VERSION
 fn state_transition(resources: f32, state: TreeState, tree: &TreeType) -> (f32, TreeState) {
   use TreeState::*;
@@ -179,93 +256,97 @@ fn state_transition(resources: f32, state: TreeState, tree: &TreeType) -> (f32,
 }
 */
 
-pub fn place_tree_zero(world: &mut World) {
-  let position = Position::zero();
-  let tc = TreeConfig::load();
-  let my_tree = tc.spieces[0].clone();
-  world.push((
-      position,
-      my_tree,
-      TreeProperties {
-        age: 0.0,
-        resources: 20.0,
-        size: 0.0,
-        state: TreeState::Sleep,
-        negative_temprature_constant_time: 0.0
-      }
-  ));
+pub fn find_species(registry: &SpeciesRegistry, species_name: &str) -> Result<SpeciesId, String> {
+  registry.find(species_name).ok_or(format!("unknown species {}", species_name))
 }
+
 pub fn place_tree_test(
   world: &mut World, 
-  p: Position, 
+  registry: &SpeciesRegistry,
+  species_name: &str,
+  position: Position, 
   age: f32, 
   size: f32
-) {
-  let position = Position::zero();
-  let tc = TreeConfig::load();
-  let my_tree = tc.spieces[0].clone();
-  world.push((
+) -> Result<Entity, String> {
+  let species = find_species(registry, species_name)?;
+  Ok(world.push((
       position,
-      my_tree,
-      TreeProperties {
-        age,
-        resources: 20.0,
-        size,
-        state: TreeState::Sleep,
-        negative_temprature_constant_time: 0.0
-      }
-
-  ));
+      species,
+      TreeProperties::new(age, size),
+      Genome::default(),
+      Dimensions::of(&TreeProperties::new(age, size), registry.get(species))
+  )))
 }
-pub fn place_tree(pusher: &mut CommandBuffer, position: Position, my_tree: TreeType) {
-  let tc = TreeConfig::load();
+
+pub fn place_tree(pusher: &mut CommandBuffer, position: Position, species: SpeciesId, genome: Genome) -> Entity {
   pusher.push((
       position,
-      my_tree,
-      TreeProperties {
-        age: 0.0,
-        resources: 20.0,
-        size: 0.0,
-        state: TreeState::Sleep,
-        negative_temprature_constant_time: 0.0
-      }
-
-  ));
+      species,
+      TreeProperties::new(0.0, 0.0),
+      genome,
+      Dimensions::default()
+  ))
 }
 
-fn get_uniform_around<T>(point: T, thres: T) -> T 
+fn get_uniform_around<T, R>(point: T, thres: T, rng: &mut R) -> T 
 where
-  T:SampleUniform + Copy + Add<Output =T>+Sub<Output =T>+From<f32>
+  T:SampleUniform + Copy + Add<Output =T>+Sub<Output =T>+From<f32>,
+  R: Rng
 {
 
   let dist = Uniform::from(point - thres .. point + thres);
-  let mut rng = rand::thread_rng();
-  dist.sample(&mut rng)
+  dist.sample(rng)
 }
 
-fn get_position_around_tree(position: &Position, tree_type: &TreeType, tree: &TreeProperties) -> Position {
-  let radius = tree.size * 0.7;
-  let random_shift = Position(Vector3::new(
-      get_uniform_around(0.0, radius), 
-      get_uniform_around(0.0, radius), 
-      0.0));
-  *position + random_shift
-
+fn get_position_around_tree<R: Rng>(
+  position: &Position,
+  tree_type: &TreeType,
+  tree: &TreeProperties,
+  map: &Map,
+  wind: &Wind,
+  rng: &mut R
+) -> Option<Position> {
+  disperse(&tree_type.dispersal, position, crown_radius(tree, tree_type), wind, map, rng)
 }
 
-fn seed_new_trees(position: &Position, tree: &TreeProperties, tree_type: &TreeType, pusher: &mut CommandBuffer) {
-  let amount = get_uniform_around(tree_type.fertility, tree_type.fertility / 3.0) as u16;
+// Seeds go into the seed bank and germinate after dormancy
+#[allow(clippy::too_many_arguments)]
+fn seed_new_trees(
+  entity: &Entity,
+  position: &Position,
+  tree: &TreeProperties,
+  species: SpeciesId,
+  tree_type: &TreeType,
+  genome: &Genome,
+  pollen: &[Genome],
+  map: &Map,
+  weather: &Weather,
+  seed_bank: &mut SeedBank,
+  rng: &mut SimRng,
+) {
+  let fertility = tree_type.fertility * genome.fertility;
+  let amount = get_uniform_around(fertility, fertility / 3.0, rng) as u16;
   println!("seed {} trees", amount);
   for _ in 0..amount {
-    let new_position = get_position_around_tree(position, tree_type, tree);
-    place_tree(pusher, new_position, tree_type.clone());
+    if let Some(new_position) = get_position_around_tree(position, tree_type, tree, map, &weather.wind, rng) {
+      seed_bank.deposit(&new_position, species, 1.0, &pick_parents(genome, pollen, rng), Some(*entity));
+    }
   }
 }
 
-fn place_offsprings(entity: &Entity, tree: &TreeProperties, tree_type: &TreeType, command_buffer: &mut CommandBuffer) {
+fn place_offsprings(
+  entity: &Entity,
+  tree: &TreeProperties,
+  tree_type: &TreeType,
+  genome: &Genome,
+  command_buffer: &mut CommandBuffer,
+  events: &mut Events
+) {
   println!("place Offsprings");
 
-  command_buffer.add_component(*entity, tree_type.offspring(tree));
+  let offspring = tree_type.offspring(tree, genome);
+  events.emit(Event::OffspringCreated { entity: *entity, amount: offspring.amount });
+  command_buffer.add_component(*entity, offspring);
 }
 fn remove_offsprings(entity: &Entity, command_buffer: &mut CommandBuffer) {
   println!("remove Offsprings");
@@ -273,13 +354,24 @@ fn remove_offsprings(entity: &Entity, command_buffer: &mut CommandBuffer) {
 }
 
 #[system(for_each)]
+#[allow(clippy::too_many_arguments)]
 pub fn drop_fruits(
+  entity: &Entity,
   position: &Position,
-  tree_type: & TreeType,
+  species: &SpeciesId,
   tree: &TreeProperties,
+  genome: &Genome,
   offspring: &mut Offspring,
   command_buffer: &mut CommandBuffer,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] settings: &Settings,
+  #[resource] map: &Map,
+  #[resource] weather: &Weather,
+  #[resource] fruit_layer: &mut FruitLayer,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
 ) {
+  let tree_type = registry.get(*species);
   if let Some(fruit_name) = &tree_type.fruit_name {
     if offspring.maturity > 0.7 && offspring.amount > 0 {
       let lvl = 0.7;
@@ -290,7 +382,20 @@ pub fn drop_fruits(
       println!("mature oak spawns {}/{} {} ", amount, offspring.amount, fruit_name);
       offspring.amount -= amount;
       for _ in 0..amount {
-        command_buffer.push((Fruit{name: fruit_name.to_owned()}, get_position_around_tree(position, tree_type, tree)));
+        if let Some(fruit_position) = get_position_around_tree(position, tree_type, tree, map, &weather.wind, rng) {
+          let parents = pick_parents(genome, &offspring.pollen, rng);
+          let fruit = match settings.fruit_representation {
+            FruitRepresentation::Entities => {
+              let fruit = Fruit { parents: parents.clone(), ..Fruit::new(*species, tree_type) };
+              Some(command_buffer.push((fruit, fruit_position)))
+            },
+            FruitRepresentation::Layer => {
+              fruit_layer.drop(&fruit_position, *species, 1, &parents);
+              None
+            }
+          };
+          events.emit(Event::FruitDropped { parent: *entity, entity: fruit, position: fruit_position });
+        }
       }
     }
   }
@@ -298,52 +403,106 @@ pub fn drop_fruits(
 
 #[system(par_for_each)]
 pub fn update_offspring(
-  tree_type: & TreeType,
+  species: &SpeciesId,
+  properties: &TreeProperties,
+  genome: &Genome,
   offspring: &mut Offspring,
   #[resource] time: &Timers,
+  #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
 ){
-  let cur_time = time.time_of_year - tree_type.ripening_start_year_time;
-  let cur_time_normalized = cur_time / (tree_type.fall_start_year_time - tree_type.ripening_start_year_time);
-  println!("cur_time_normalized: {}", cur_time_normalized);
-  offspring.maturity = cur_time_normalized;
+  let tree_type = registry.get(*species);
+  // fruits are ripe while the tree is falling
+  offspring.maturity = match settings.phenology_model {
+    _ if properties.state == TreeState::Falling => 1.0,
+    PhenologyModel::Calendar => {
+      let year_time = tree_type.year_time(time.time_of_year, settings.hemisphere, genome);
+      tree_type.phases.progress(year_time)
+    },
+    PhenologyModel::DegreeDays => degree_day_maturity(properties, &tree_type.phenology, genome)
+  };
 }
 
 #[system(for_each)]
+#[allow(clippy::too_many_arguments)]
 pub fn update_trees(
   entity: &Entity,
-  tree_type: &TreeType, 
+  species: &SpeciesId, 
   properties: &mut TreeProperties,
   position: &Position,
+  genome: &Genome,
+  offspring: Option<&Offspring>,
   command_buffer: &mut CommandBuffer,
   #[resource] time: &Timers,
   #[resource] settings: &Settings,
+  #[resource] registry: &SpeciesRegistry,
+  #[resource] map: &Map,
+  #[resource] weather: &Weather,
+  #[resource] seed_bank: &mut SeedBank,
+  #[resource] rng: &mut SimRng,
+  #[resource] events: &mut Events,
   ) {
+  if properties.dead {
+    return;
+  }
+  let tree_type = registry.get(*species);
   properties.age += time.long.elapsed_seconds;
   let years = properties.age / settings.seconds_in_year();
 
-  let new_state = if years > tree_type.maturity_years {
-    state_transition(time.time_of_year, tree_type)
-  } else {
-    inmature_state_transition(time.time_of_year, tree_type)
+  let (phase, new_state) = match settings.phenology_model {
+    PhenologyModel::Calendar => {
+      let phase = select_phase(tree_type.year_time(time.time_of_year, settings.hemisphere, genome), tree_type);
+      (phase, tree_type.phases.get(phase).state.clone())
+    },
+    // Phases have fixed starts in the year, so weather driven states only
+    // name the phase
+    PhenologyModel::DegreeDays => {
+      let tempreture = weather.current_tempreture;
+      accumulate_thermal_time(properties, &tree_type.phenology, tempreture, time.long.elapsed_seconds, settings);
+      let state = degree_day_state(properties, &tree_type.phenology, genome, tempreture);
+      (tree_type.phases.of_state(&state).unwrap_or(properties.phase), state)
+    }
+  };
+  let new_state = if years > tree_type.maturity_years { new_state } else { inmature(new_state) };
+  let new_state = early_leaf_fall(properties, tree_type, new_state);
+  let new_state = match settings.growth_model {
+    GrowthModel::Simple => new_state,
+    GrowthModel::ResourceBased => pay_for_transition(properties, tree_type, new_state)
   };
   println!("{:?}, age-years: {}, old state: {:?}, new_state: {:?} {}",entity, years, properties.state, new_state, time.time_of_year);
 
 
+  if properties.state != new_state {
+    events.emit(Event::TreeStateChanged {
+      entity: *entity,
+      from: properties.state.clone(),
+      to: new_state.clone()
+    });
+  }
+
   if properties.state == TreeState::Blossom && new_state == TreeState::Ripening {
-    place_offsprings(entity, properties, tree_type, command_buffer);
+    place_offsprings(entity, properties, tree_type, genome, command_buffer, events);
+  }
+
+  if properties.state != TreeState::Sleep && new_state == TreeState::Sleep {
+    start_dormancy(properties);
+    reset_pollination(properties);
+    die_back(properties, tree_type);
   }
 
   if properties.state == TreeState::Falling && new_state == TreeState::Sleep {
     remove_offsprings(entity, command_buffer);
     if years > tree_type.maturity_years {
-      seed_new_trees(position, properties, tree_type, command_buffer);
+      let pollen = offspring.map_or(&[][..], |o| &o.pollen);
+      seed_new_trees(entity, position, properties, *species, tree_type, genome, pollen, map, weather, seed_bank, rng);
+      if tree_type.life_form.dies_after_seeding() {
+        kill_tree(entity, properties, DeathCause::Senescence, command_buffer, settings, events);
+      }
     }
   }
 
   properties.state = new_state;
-  if matches!(properties.state, TreeState::Blossom | TreeState::Ripening) && properties.size <= tree_type.max_size {
-    properties.size += tree_type.growth_speed * time.long.elapsed_seconds;
-  }
+  properties.phase = phase;
 }
 
 
@@ -399,6 +558,12 @@ pub fn update_trees_complex(
 #[cfg(test)]
 mod test {
   use super::*;
+  use super::super::movements::Vector3;
+  use num::traits::Zero;
+
+  fn select_state(t: f32, tree: &TreeType) -> TreeState {
+    tree.phases.get(select_phase(t, tree)).state.clone()
+  }
 
   #[test]
   fn select_state_test() {
@@ -409,6 +574,47 @@ mod test {
     assert_eq!(select_state(0.501, &trees.spieces[0]), TreeState::Falling);
     assert_eq!(select_state(0.701, &trees.spieces[0]), TreeState::Sleep);
   }
+
+  #[test]
+  fn southern_trees_blossom_half_year_later() {
+    let trees = TreeConfig::load();
+    let oak = &trees.spieces[0];
+    let genome = Genome::default();
+    let state = |t, hemisphere| select_state(oak.year_time(t, hemisphere, &genome), oak);
+    assert_eq!(state(0.13, Hemisphere::Northern), TreeState::Blossom);
+    assert_eq!(state(0.13, Hemisphere::Southern), TreeState::Falling);
+    assert_eq!(state(0.63, Hemisphere::Southern), TreeState::Blossom);
+  }
+  #[test]
+  fn test_trees_are_placed_where_requested() {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    let position = Position(Vector3::new(1.0, 2.0, 0.0));
+    let entity = place_tree_test(&mut world, &registry, "oak", position, 0.0, 1.0).unwrap();
+    let entry = world.entry(entity).unwrap();
+
+    assert_eq!(entry.get_component::<Position>().unwrap(), &position);
+    assert_eq!(entry.get_component::<SpeciesId>().unwrap(), &registry.find("oak").unwrap());
+  }
+
+  #[test]
+  fn rates_outside_of_probability_are_rejected() {
+    let trees = TreeConfig::load();
+    let mut oak = trees.spieces[0].clone();
+    assert!(oak.validate().is_ok());
+    oak.background_mortality = 1.5;
+    assert!(oak.validate().is_err());
+  }
+
+  #[test]
+  fn unknown_species_is_not_placed() {
+    let registry = SpeciesRegistry::load();
+    let mut world = World::default();
+    assert!(find_species(&registry, "baobab").is_err());
+    assert!(place_tree_test(&mut world, &registry, "baobab", Position::zero(), 0.0, 1.0).is_err());
+    assert_eq!(world.len(), 0);
+  }
+
   #[test]
   fn check_clamp() {
     assert_eq!(clamp(0.5, 0.0, 1.0), 0.5);